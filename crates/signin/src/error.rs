@@ -0,0 +1,32 @@
+use anchor_lang::prelude::Pubkey;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SignInError {
+    #[error("malformed sign-in message: {0}")]
+    InvalidMessage(String),
+    #[error("message is for {0}, not this app")]
+    AppDomainMismatch(String),
+    #[error("message nonce doesn't match the challenge")]
+    NonceMismatch,
+    #[error("message has expired")]
+    Expired,
+    #[error("message isn't valid yet")]
+    NotYetValid,
+    #[error("message has no issue time")]
+    MissingIssuedAt,
+    #[error("message uri {0} isn't on this app's domain")]
+    UriMismatch(String),
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("{0} isn't linked to a keychain in this domain")]
+    KeyNotFound(Pubkey),
+    #[error("{0} isn't a keychain account")]
+    InvalidAccount(Pubkey),
+    #[error("keychain {0} isn't in this domain")]
+    KeychainDomainMismatch(Pubkey),
+    #[error("{0} isn't on the keychain")]
+    NotOnKeychain(Pubkey),
+    #[error("failed to load account {0}: {1}")]
+    Source(Pubkey, String),
+}
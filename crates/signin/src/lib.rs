@@ -0,0 +1,143 @@
+// off-chain sign in for keychain users: checks a Sign-In With Solana message was signed by a wallet, then that the
+// wallet is a key on a keychain in the given domain. accounts come from an AccountSource, so this works against rpc or
+// fixtures
+
+use anchor_lang::{AccountDeserialize, Discriminator};
+use anchor_lang::prelude::Pubkey;
+use keychain::account::{CurrentKeyChain, KeyChainKey};
+use keychain::constant::{KEYCHAIN, KEY_SPACE};
+
+pub mod error;
+pub mod message;
+pub mod source;
+
+pub use error::SignInError;
+pub use message::SignInMessage;
+pub use source::{AccountSource, RawAccount};
+
+// how long after its issue time a message can be used (unless the verifier's given another max age)
+pub const DEFAULT_MAX_AGE: i64 = 10 * 60;
+
+// what the server asked the wallet to sign
+#[derive(Debug, Clone)]
+pub struct Challenge {
+    // the app domain the message has to be for
+    pub app_domain: String,
+    pub nonce: String,
+    // only accept keys on this keychain (otherwise any keychain in the domain)
+    pub keychain: Option<Pubkey>,
+}
+
+// a verified sign in
+#[derive(Debug, Clone)]
+pub struct SignIn {
+    pub wallet: Pubkey,
+    pub keychain: Pubkey,
+    pub keychain_name: String,
+    pub message: SignInMessage,
+}
+
+pub struct Verifier<S: AccountSource> {
+    source: S,
+    program_id: Pubkey,
+    // the keychain domain
+    domain: String,
+    // seconds a message is good for after its issue time
+    max_age: i64,
+}
+
+impl<S: AccountSource> Verifier<S> {
+    pub fn new(source: S, domain: &str) -> Self {
+        Self { source, program_id: keychain::ID, domain: domain.to_string(), max_age: DEFAULT_MAX_AGE }
+    }
+
+    pub fn with_max_age(mut self, max_age: i64) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    // for keychain programs deployed somewhere else (e.g. devnet/localnet)
+    pub fn with_program_id(mut self, program_id: Pubkey) -> Self {
+        self.program_id = program_id;
+        self
+    }
+
+    // the key account (pointer to the keychain) for a wallet in this domain
+    pub fn key_address(&self, wallet: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(
+            &[wallet.as_ref(), KEY_SPACE.as_bytes(), self.domain.as_bytes(), KEYCHAIN.as_bytes()],
+            &self.program_id,
+        ).0
+    }
+
+    // verifies the message + its signature (now = unix timestamp) and returns the signing wallet's keychain
+    pub fn verify(&self, message: &str, signature: &[u8], challenge: &Challenge, now: i64) -> Result<SignIn, SignInError> {
+        let parsed = SignInMessage::parse(message)?;
+        if parsed.domain != challenge.app_domain {
+            return Err(SignInError::AppDomainMismatch(parsed.domain));
+        }
+        parsed.check_uri(&challenge.app_domain)?;
+        if parsed.nonce.as_deref() != Some(challenge.nonce.as_str()) {
+            return Err(SignInError::NonceMismatch);
+        }
+        parsed.check_time(now, self.max_age)?;
+        verify_signature(&parsed.address, message.as_bytes(), signature)?;
+
+        let wallet = parsed.address;
+        let key_address = self.key_address(&wallet);
+        let keychain_key = self.load_key(&key_address)?.ok_or(SignInError::KeyNotFound(wallet))?;
+        if keychain_key.key != wallet {
+            return Err(SignInError::InvalidAccount(key_address));
+        }
+        if matches!(challenge.keychain, Some(keychain) if keychain != keychain_key.keychain) {
+            return Err(SignInError::NotOnKeychain(wallet));
+        }
+
+        // the key account can outlive the keychain (or the key's membership) on older versions, so check both
+        let keychain: CurrentKeyChain = self.load(&keychain_key.keychain)?.ok_or(SignInError::NotOnKeychain(wallet))?;
+        if keychain.domain != self.domain {
+            return Err(SignInError::KeychainDomainMismatch(keychain_key.keychain));
+        }
+        if !keychain.has_key(&wallet) {
+            return Err(SignInError::NotOnKeychain(wallet));
+        }
+
+        Ok(SignIn { wallet, keychain: keychain_key.keychain, keychain_name: keychain.name, message: parsed })
+    }
+
+    // key accounts created before they were versioned (and not yet migrated) are just the keychain + key
+    fn load_key(&self, address: &Pubkey) -> Result<Option<KeyChainKey>, SignInError> {
+        match self.load::<KeyChainKey>(address) {
+            Err(SignInError::InvalidAccount(_)) => {},
+            result => return result,
+        }
+        let account = self.source.get_account(address)?.ok_or(SignInError::InvalidAccount(*address))?;
+        let data = account.data.as_slice();
+        if account.owner != self.program_id || data.len() < 8 + 64 || data[..8] != KeyChainKey::discriminator() {
+            return Err(SignInError::InvalidAccount(*address));
+        }
+        let keychain = Pubkey::try_from(&data[8..40]).map_err(|_| SignInError::InvalidAccount(*address))?;
+        let key = Pubkey::try_from(&data[40..72]).map_err(|_| SignInError::InvalidAccount(*address))?;
+        Ok(Some(KeyChainKey { keychain, key, bump: 0, version: 0 }))
+    }
+
+    // loads + decodes an account owned by the keychain program (checking the discriminator)
+    fn load<T: AccountDeserialize>(&self, address: &Pubkey) -> Result<Option<T>, SignInError> {
+        let account = match self.source.get_account(address)? {
+            Some(account) => account,
+            None => return Ok(None),
+        };
+        if account.owner != self.program_id {
+            return Err(SignInError::InvalidAccount(*address));
+        }
+        T::try_deserialize(&mut account.data.as_slice())
+            .map(Some)
+            .map_err(|_| SignInError::InvalidAccount(*address))
+    }
+}
+
+pub fn verify_signature(wallet: &Pubkey, message: &[u8], signature: &[u8]) -> Result<(), SignInError> {
+    let public_key = ed25519_dalek::PublicKey::from_bytes(wallet.as_ref()).map_err(|_| SignInError::InvalidSignature)?;
+    let signature = ed25519_dalek::Signature::from_bytes(signature).map_err(|_| SignInError::InvalidSignature)?;
+    public_key.verify_strict(message, &signature).map_err(|_| SignInError::InvalidSignature)
+}
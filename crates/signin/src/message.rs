@@ -0,0 +1,129 @@
+use std::str::FromStr;
+use anchor_lang::prelude::Pubkey;
+use chrono::DateTime;
+use crate::error::SignInError;
+
+const HEADER_SUFFIX: &str = " wants you to sign in with your Solana account:";
+
+// a Sign-In With Solana message (https://github.com/phantom/sign-in-with-solana), e.g.
+//
+//   example.com wants you to sign in with your Solana account:
+//   <address>
+//
+//   <statement>
+//
+//   URI: https://example.com/login
+//   Version: 1
+//   Nonce: 32891756
+//   Issued At: 2023-05-01T00:00:00Z
+//   Expiration Time: 2023-05-01T00:10:00Z
+//
+// times are converted to unix timestamps. fields we don't check (chain id, request id, resources) are skipped
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignInMessage {
+    // the app asking for the sign in (not the keychain domain)
+    pub domain: String,
+    pub address: Pubkey,
+    pub statement: Option<String>,
+    pub uri: Option<String>,
+    pub version: Option<String>,
+    pub chain_id: Option<String>,
+    pub nonce: Option<String>,
+    pub issued_at: Option<i64>,
+    pub expiration_time: Option<i64>,
+    pub not_before: Option<i64>,
+}
+
+impl SignInMessage {
+    pub fn parse(message: &str) -> Result<Self, SignInError> {
+        let mut lines = message.lines();
+
+        let domain = lines.next()
+            .and_then(|line| line.strip_suffix(HEADER_SUFFIX))
+            .filter(|domain| !domain.is_empty())
+            .ok_or_else(|| SignInError::InvalidMessage("missing header".to_string()))?;
+        let address = lines.next()
+            .and_then(|line| Pubkey::from_str(line.trim()).ok())
+            .ok_or_else(|| SignInError::InvalidMessage("missing or invalid address".to_string()))?;
+
+        let mut parsed = Self {
+            domain: domain.to_string(),
+            address,
+            statement: None,
+            uri: None,
+            version: None,
+            chain_id: None,
+            nonce: None,
+            issued_at: None,
+            expiration_time: None,
+            not_before: None,
+        };
+
+        let mut in_fields = false;
+        for line in lines {
+            if line.is_empty() || line.starts_with("- ") {
+                continue;
+            }
+            let field = line.split_once(": ").or_else(|| line.strip_suffix(':').map(|name| (name, "")));
+            match field {
+                Some(("URI", value)) => parsed.uri = Some(value.to_string()),
+                Some(("Version", value)) => parsed.version = Some(value.to_string()),
+                Some(("Chain ID", value)) => parsed.chain_id = Some(value.to_string()),
+                Some(("Nonce", value)) => parsed.nonce = Some(value.to_string()),
+                Some(("Issued At", value)) => parsed.issued_at = Some(parse_time(value)?),
+                Some(("Expiration Time", value)) => parsed.expiration_time = Some(parse_time(value)?),
+                Some(("Not Before", value)) => parsed.not_before = Some(parse_time(value)?),
+                Some(("Request ID", _)) | Some(("Resources", _)) => {},
+                // the statement is the only free text, and comes before the fields
+                _ if !in_fields && parsed.statement.is_none() => {
+                    parsed.statement = Some(line.to_string());
+                    continue;
+                },
+                _ => return Err(SignInError::InvalidMessage(format!("unexpected line: {}", line))),
+            }
+            in_fields = true;
+        }
+
+        Ok(parsed)
+    }
+
+    // checks the message is within its validity window: it has to have an issue time that's less than max_age seconds
+    // ago (and not in the future), plus its expiration time and not before if it has them. an expiration time alone
+    // isn't enough, since it could be any time in the future
+    pub fn check_time(&self, now: i64, max_age: i64) -> Result<(), SignInError> {
+        let issued_at = self.issued_at.ok_or(SignInError::MissingIssuedAt)?;
+        if now - issued_at >= max_age {
+            return Err(SignInError::Expired);
+        }
+        if matches!(self.expiration_time, Some(expiration_time) if now >= expiration_time) {
+            return Err(SignInError::Expired);
+        }
+        if now < issued_at || matches!(self.not_before, Some(not_before) if now < not_before) {
+            return Err(SignInError::NotYetValid);
+        }
+        Ok(())
+    }
+
+    // checks the uri is on the app domain, e.g. https://example.com/login for example.com
+    pub fn check_uri(&self, app_domain: &str) -> Result<(), SignInError> {
+        let uri = self.uri.as_deref().ok_or_else(|| SignInError::InvalidMessage("missing uri".to_string()))?;
+        if uri_authority(uri) != Some(app_domain) {
+            return Err(SignInError::UriMismatch(uri.to_string()));
+        }
+        Ok(())
+    }
+}
+
+// the host (and port) of a uri like scheme://[user@]host[:port][/path][?query][#fragment]
+fn uri_authority(uri: &str) -> Option<&str> {
+    let (_, rest) = uri.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    Some(host).filter(|host| !host.is_empty())
+}
+
+fn parse_time(value: &str) -> Result<i64, SignInError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.timestamp())
+        .map_err(|_| SignInError::InvalidMessage(format!("invalid time: {}", value)))
+}
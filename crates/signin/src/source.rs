@@ -0,0 +1,27 @@
+use std::collections::HashMap;
+use anchor_lang::prelude::Pubkey;
+use crate::error::SignInError;
+
+// the parts of an account the verifier needs
+#[derive(Debug, Clone)]
+pub struct RawAccount {
+    pub owner: Pubkey,
+    pub data: Vec<u8>,
+}
+
+// where the verifier loads accounts from - an rpc client in production, fixtures in tests. a missing account is Ok(None)
+pub trait AccountSource {
+    fn get_account(&self, address: &Pubkey) -> Result<Option<RawAccount>, SignInError>;
+}
+
+impl AccountSource for HashMap<Pubkey, RawAccount> {
+    fn get_account(&self, address: &Pubkey) -> Result<Option<RawAccount>, SignInError> {
+        Ok(self.get(address).cloned())
+    }
+}
+
+impl<T: AccountSource + ?Sized> AccountSource for &T {
+    fn get_account(&self, address: &Pubkey) -> Result<Option<RawAccount>, SignInError> {
+        (**self).get_account(address)
+    }
+}
@@ -0,0 +1,241 @@
+// verifies sign ins against fixture accounts (a HashMap AccountSource)
+
+use std::collections::HashMap;
+use anchor_lang::{AccountSerialize, Discriminator};
+use anchor_lang::prelude::Pubkey;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer};
+use keychain::account::{CurrentKeyChain, KeyChainKey, UserKey};
+use keychain::constant::CURRENT_KEY_VERSION;
+use keychain_signin::{Challenge, RawAccount, SignInError, Verifier};
+
+const DOMAIN: &str = "testdomain";
+const APP_DOMAIN: &str = "example.com";
+const NONCE: &str = "32891756";
+// 2023-05-01T00:05:00Z
+const NOW: i64 = 1682899500;
+
+fn wallet(seed: u8) -> Keypair {
+    let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+}
+
+fn address(keypair: &Keypair) -> Pubkey {
+    Pubkey::new_from_array(keypair.public.to_bytes())
+}
+
+fn message(app_domain: &str, wallet: &Pubkey, nonce: &str, times: &[(&str, &str)]) -> String {
+    let mut message = format!(
+        "{} wants you to sign in with your Solana account:\n{}\n\nSign in to play\n\nURI: https://{}/login\nVersion: 1\nNonce: {}",
+        app_domain, wallet, app_domain, nonce,
+    );
+    for (field, time) in times {
+        message.push_str(&format!("\n{}: {}", field, time));
+    }
+    message
+}
+
+// issued a minute before NOW, good for ten
+fn valid_message(wallet: &Pubkey) -> String {
+    message(APP_DOMAIN, wallet, NONCE, &[("Issued At", "2023-05-01T00:04:00Z"), ("Expiration Time", "2023-05-01T00:14:00Z")])
+}
+
+fn sign(keypair: &Keypair, message: &str) -> Vec<u8> {
+    keypair.sign(message.as_bytes()).to_bytes().to_vec()
+}
+
+fn challenge() -> Challenge {
+    Challenge { app_domain: APP_DOMAIN.to_string(), nonce: NONCE.to_string(), keychain: None }
+}
+
+fn account<T: AccountSerialize>(account: &T) -> RawAccount {
+    let mut data = Vec::new();
+    account.try_serialize(&mut data).unwrap();
+    RawAccount { owner: keychain::ID, data }
+}
+
+fn keychain_account(domain: &str, keys: &[Pubkey]) -> RawAccount {
+    account(&CurrentKeyChain {
+        name: "player".to_string(),
+        num_keys: keys.len() as u16,
+        domain: domain.to_string(),
+        bump: 255,
+        keys: keys.iter().map(|key| UserKey::new(*key)).collect(),
+    })
+}
+
+// a keychain with the given keys, plus a key account for each pointing at it
+fn fixtures(keychain: Pubkey, keys: &[Pubkey]) -> HashMap<Pubkey, RawAccount> {
+    let verifier = Verifier::new(HashMap::new(), DOMAIN);
+    let mut accounts = HashMap::new();
+    accounts.insert(keychain, keychain_account(DOMAIN, keys));
+    for key in keys {
+        accounts.insert(verifier.key_address(key), account(&KeyChainKey {
+            keychain,
+            key: *key,
+            bump: 255,
+            version: CURRENT_KEY_VERSION,
+        }));
+    }
+    accounts
+}
+
+#[test]
+fn verifies_a_valid_sign_in() {
+    let keypair = wallet(1);
+    let wallet = address(&keypair);
+    let keychain = Pubkey::new_unique();
+    let accounts = fixtures(keychain, &[wallet]);
+    let message = valid_message(&wallet);
+
+    let sign_in = Verifier::new(&accounts, DOMAIN).verify(&message, &sign(&keypair, &message), &challenge(), NOW).unwrap();
+    assert_eq!(sign_in.wallet, wallet);
+    assert_eq!(sign_in.keychain, keychain);
+    assert_eq!(sign_in.keychain_name, "player");
+    assert_eq!(sign_in.message.statement.as_deref(), Some("Sign in to play"));
+
+    // also when the challenge asks for that keychain
+    let challenge = Challenge { keychain: Some(keychain), ..challenge() };
+    assert!(Verifier::new(&accounts, DOMAIN).verify(&message, &sign(&keypair, &message), &challenge, NOW).is_ok());
+}
+
+#[test]
+fn rejects_a_bad_signature() {
+    let keypair = wallet(1);
+    let wallet = address(&keypair);
+    let accounts = fixtures(Pubkey::new_unique(), &[wallet]);
+    let verifier = Verifier::new(&accounts, DOMAIN);
+    let message = valid_message(&wallet);
+
+    // signed by someone else
+    let result = verifier.verify(&message, &sign(&self::wallet(2), &message), &challenge(), NOW);
+    assert!(matches!(result, Err(SignInError::InvalidSignature)));
+
+    // signed over a different message
+    let other = message.replace("Sign in to play", "Sign in to pay");
+    let result = verifier.verify(&message, &sign(&keypair, &other), &challenge(), NOW);
+    assert!(matches!(result, Err(SignInError::InvalidSignature)));
+
+    let result = verifier.verify(&message, &[0; 10], &challenge(), NOW);
+    assert!(matches!(result, Err(SignInError::InvalidSignature)));
+}
+
+#[test]
+fn rejects_the_wrong_nonce_or_app_domain() {
+    let keypair = wallet(1);
+    let wallet = address(&keypair);
+    let accounts = fixtures(Pubkey::new_unique(), &[wallet]);
+    let verifier = Verifier::new(&accounts, DOMAIN);
+    let times = [("Issued At", "2023-05-01T00:04:00Z")];
+
+    let message = message(APP_DOMAIN, &wallet, "11111111", &times);
+    let result = verifier.verify(&message, &sign(&keypair, &message), &challenge(), NOW);
+    assert!(matches!(result, Err(SignInError::NonceMismatch)));
+
+    let message = self::message("evil.com", &wallet, NONCE, &times);
+    let result = verifier.verify(&message, &sign(&keypair, &message), &challenge(), NOW);
+    assert!(matches!(result, Err(SignInError::AppDomainMismatch(domain)) if domain == "evil.com"));
+}
+
+#[test]
+fn rejects_a_message_outside_its_validity_window() {
+    let keypair = wallet(1);
+    let wallet = address(&keypair);
+    let accounts = fixtures(Pubkey::new_unique(), &[wallet]);
+    let verifier = Verifier::new(&accounts, DOMAIN);
+    let verify = |times: &[(&str, &str)]| {
+        let message = message(APP_DOMAIN, &wallet, NONCE, times);
+        verifier.verify(&message, &sign(&keypair, &message), &challenge(), NOW)
+    };
+
+    assert!(matches!(verify(&[("Issued At", "2023-05-01T00:00:00Z"), ("Expiration Time", "2023-05-01T00:04:59Z")]), Err(SignInError::Expired)));
+    assert!(matches!(verify(&[("Issued At", "2023-05-01T00:04:00Z"), ("Not Before", "2023-05-01T00:06:00Z")]), Err(SignInError::NotYetValid)));
+    assert!(matches!(verify(&[("Issued At", "2023-05-01T00:06:00Z")]), Err(SignInError::NotYetValid)));
+
+    // issued longer ago than the max age
+    assert!(matches!(verify(&[("Issued At", "2023-04-30T23:55:00Z")]), Err(SignInError::Expired)));
+    assert!(verify(&[("Issued At", "2023-04-30T23:55:01Z")]).is_ok());
+    let message = message(APP_DOMAIN, &wallet, NONCE, &[("Issued At", "2023-05-01T00:04:00Z")]);
+    let result = Verifier::new(&accounts, DOMAIN).with_max_age(30).verify(&message, &sign(&keypair, &message), &challenge(), NOW);
+    assert!(matches!(result, Err(SignInError::Expired)));
+
+    // no issue time, so nothing bounds how long it's good for - even with an expiration time
+    assert!(matches!(verify(&[]), Err(SignInError::MissingIssuedAt)));
+    assert!(matches!(verify(&[("Expiration Time", "2023-05-01T00:14:00Z")]), Err(SignInError::MissingIssuedAt)));
+    assert!(matches!(verify(&[("Expiration Time", "2099-01-01T00:00:00Z")]), Err(SignInError::MissingIssuedAt)));
+}
+
+#[test]
+fn rejects_a_uri_off_the_app_domain() {
+    let keypair = wallet(1);
+    let wallet = address(&keypair);
+    let accounts = fixtures(Pubkey::new_unique(), &[wallet]);
+    let verifier = Verifier::new(&accounts, DOMAIN);
+    let verify = |uri: &str| {
+        let message = valid_message(&wallet).replace("URI: https://example.com/login", uri);
+        verifier.verify(&message, &sign(&keypair, &message), &challenge(), NOW)
+    };
+
+    assert!(verify("URI: https://example.com").is_ok());
+    assert!(verify("URI: https://example.com/login?next=/play#top").is_ok());
+    for uri in ["https://evil.com/login", "https://example.com.evil.com/login", "https://example.com@evil.com/login", "example.com/login"] {
+        let result = verify(&format!("URI: {}", uri));
+        assert!(matches!(result, Err(SignInError::UriMismatch(mismatch)) if mismatch == uri), "{}", uri);
+    }
+    assert!(matches!(verify(""), Err(SignInError::InvalidMessage(_))));
+}
+
+#[test]
+fn rejects_a_key_not_on_the_keychain() {
+    let keypair = wallet(1);
+    let wallet = address(&keypair);
+    let keychain = Pubkey::new_unique();
+    let message = valid_message(&wallet);
+    let signature = sign(&keypair, &message);
+
+    // no key account
+    let accounts = fixtures(keychain, &[Pubkey::new_unique()]);
+    let result = Verifier::new(&accounts, DOMAIN).verify(&message, &signature, &challenge(), NOW);
+    assert!(matches!(result, Err(SignInError::KeyNotFound(key)) if key == wallet));
+
+    // a key account left pointing at a keychain the key's been removed from
+    let mut accounts = fixtures(keychain, &[wallet]);
+    accounts.insert(keychain, keychain_account(DOMAIN, &[Pubkey::new_unique()]));
+    let result = Verifier::new(&accounts, DOMAIN).verify(&message, &signature, &challenge(), NOW);
+    assert!(matches!(result, Err(SignInError::NotOnKeychain(key)) if key == wallet));
+
+    // on a keychain, but not the one asked for
+    let accounts = fixtures(keychain, &[wallet]);
+    let other_keychain = Challenge { keychain: Some(Pubkey::new_unique()), ..challenge() };
+    let result = Verifier::new(&accounts, DOMAIN).verify(&message, &signature, &other_keychain, NOW);
+    assert!(matches!(result, Err(SignInError::NotOnKeychain(key)) if key == wallet));
+
+    // a keychain in another domain
+    let mut accounts = fixtures(keychain, &[wallet]);
+    accounts.insert(keychain, keychain_account("otherdomain", &[wallet]));
+    let result = Verifier::new(&accounts, DOMAIN).verify(&message, &signature, &challenge(), NOW);
+    assert!(matches!(result, Err(SignInError::KeychainDomainMismatch(address)) if address == keychain));
+}
+
+#[test]
+fn reads_unmigrated_key_accounts() {
+    let keypair = wallet(1);
+    let wallet = address(&keypair);
+    let keychain = Pubkey::new_unique();
+    let mut accounts = fixtures(keychain, &[wallet]);
+    let message = valid_message(&wallet);
+    let signature = sign(&keypair, &message);
+
+    // from before key accounts had a bump + version: just the keychain + key
+    let key_address = Verifier::new(&accounts, DOMAIN).key_address(&wallet);
+    let data = [&KeyChainKey::discriminator()[..], keychain.as_ref(), wallet.as_ref()].concat();
+    accounts.insert(key_address, RawAccount { owner: keychain::ID, data });
+    let sign_in = Verifier::new(&accounts, DOMAIN).verify(&message, &signature, &challenge(), NOW).unwrap();
+    assert_eq!(sign_in.keychain, keychain);
+
+    // but it still has to be the keychain program's
+    let account = accounts.get_mut(&key_address).unwrap();
+    account.owner = Pubkey::new_unique();
+    let result = Verifier::new(&accounts, DOMAIN).verify(&message, &signature, &challenge(), NOW);
+    assert!(matches!(result, Err(SignInError::InvalidAccount(address)) if address == key_address));
+}
@@ -0,0 +1,100 @@
+// exports a domain's keychains, keychain states and keys (JSON or CSV) and reports anything inconsistent between them
+//
+//   keychain-snapshot <domain> (--rpc <url> | --dir <account dump dir>) [--program-id <id>] [--format json|csv] [--out <path>]
+//
+// json goes to stdout (or --out), csv writes one file per table to the --out directory (default: current directory).
+// exits with 2 if any issues were found
+
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use anchor_lang::prelude::Pubkey;
+
+mod output;
+mod snapshot;
+mod source;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const USAGE: &str = "usage: keychain-snapshot <domain> (--rpc <url> | --dir <account dump dir>) [--program-id <id>] [--format json|csv] [--out <path>]";
+
+enum Source {
+    Rpc(String),
+    Dir(PathBuf),
+}
+
+struct Args {
+    domain: String,
+    source: Source,
+    program_id: Pubkey,
+    csv: bool,
+    out: Option<PathBuf>,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut args = std::env::args().skip(1);
+    let mut domain = None;
+    let mut source = None;
+    let mut program_id = keychain::ID;
+    let mut csv = false;
+    let mut out = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--rpc" => source = Some(Source::Rpc(value()?)),
+            "--dir" => source = Some(Source::Dir(PathBuf::from(value()?))),
+            "--program-id" => program_id = Pubkey::from_str(&value()?)?,
+            "--format" => csv = match value()?.as_str() {
+                "json" => false,
+                "csv" => true,
+                format => return Err(format!("unknown format: {}", format).into()),
+            },
+            "--out" => out = Some(PathBuf::from(value()?)),
+            _ if domain.is_none() && !arg.starts_with("--") => domain = Some(arg),
+            _ => return Err(format!("unexpected argument: {}", arg).into()),
+        }
+    }
+
+    Ok(Args {
+        domain: domain.ok_or("missing domain")?,
+        source: source.ok_or("one of --rpc or --dir is required")?,
+        program_id,
+        csv,
+        out,
+    })
+}
+
+fn run() -> Result<bool> {
+    let args = parse_args()?;
+
+    let accounts = match &args.source {
+        Source::Rpc(url) => source::load_rpc(url, &args.program_id)?,
+        Source::Dir(dir) => source::load_dir(dir)?,
+    };
+    let snapshot = snapshot::build(&args.domain, &args.program_id, &accounts);
+
+    if args.csv {
+        output::write_csv(&snapshot, &args.out.unwrap_or_else(|| PathBuf::from(".")))?;
+    } else {
+        output::write_json(&snapshot, args.out.as_deref())?;
+    }
+
+    eprintln!("{}: {} keychains, {} keys, {} v1 keychains, {} issues", snapshot.domain, snapshot.keychains.len(),
+              snapshot.keys.len(), snapshot.v1_keychains.len(), snapshot.issues.len());
+    for issue in snapshot.issues.iter() {
+        eprintln!("  {} {}: {}", issue.kind, issue.account, issue.detail);
+    }
+    Ok(snapshot.issues.is_empty())
+}
+
+fn main() {
+    match run() {
+        Ok(true) => {},
+        Ok(false) => process::exit(2),
+        Err(err) => {
+            eprintln!("error: {}\n{}", err, USAGE);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use crate::Result;
+use crate::snapshot::Snapshot;
+
+// the whole snapshot as one json document (stdout if there's no path)
+pub fn write_json(snapshot: &Snapshot, path: Option<&Path>) -> Result<()> {
+    let writer: Box<dyn Write> = match path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    serde_json::to_writer_pretty(writer, snapshot)?;
+    Ok(())
+}
+
+// one csv file per table in the given directory
+pub fn write_csv(snapshot: &Snapshot, dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let mut keychains = csv_file(dir, "keychains.csv", &["address", "name", "num_keys", "keys", "state", "version",
+        "action_threshold", "display_name", "pending_action", "beneficiary", "last_active"])?;
+    for keychain in snapshot.keychains.iter() {
+        // key:weight, with a * for program-controlled keys
+        let keys: Vec<String> = keychain.keys.iter()
+            .map(|key| format!("{}:{}{}", key.key, key.weight, if key.program_controlled { "*" } else { "" }))
+            .collect();
+        write_row(&mut keychains, &[
+            keychain.address.clone(),
+            keychain.name.clone(),
+            keychain.num_keys.to_string(),
+            keys.join(";"),
+            optional(&keychain.state),
+            optional(&keychain.version),
+            optional(&keychain.action_threshold),
+            optional(&keychain.display_name),
+            optional(&keychain.pending_action),
+            optional(&keychain.beneficiary),
+            optional(&keychain.last_active),
+        ])?;
+    }
+
+    let mut keys = csv_file(dir, "keys.csv", &["address", "key", "keychain"])?;
+    for key in snapshot.keys.iter() {
+        write_row(&mut keys, &[key.address.clone(), key.key.clone(), key.keychain.clone()])?;
+    }
+
+    let mut v1_keychains = csv_file(dir, "v1_keychains.csv", &["address", "num_keys", "keys"])?;
+    for keychain in snapshot.v1_keychains.iter() {
+        write_row(&mut v1_keychains, &[keychain.address.clone(), keychain.num_keys.to_string(), keychain.keys.join(";")])?;
+    }
+
+    let mut issues = csv_file(dir, "issues.csv", &["kind", "account", "detail"])?;
+    for issue in snapshot.issues.iter() {
+        write_row(&mut issues, &[issue.kind.to_string(), issue.account.clone(), issue.detail.clone()])?;
+    }
+
+    Ok(())
+}
+
+fn csv_file(dir: &Path, name: &str, header: &[&str]) -> Result<BufWriter<File>> {
+    let mut file = BufWriter::new(File::create(dir.join(name))?);
+    writeln!(file, "{}", header.join(","))?;
+    Ok(file)
+}
+
+fn write_row(file: &mut BufWriter<File>, fields: &[String]) -> Result<()> {
+    let fields: Vec<String> = fields.iter().map(|field| escape(field)).collect();
+    writeln!(file, "{}", fields.join(","))?;
+    Ok(())
+}
+
+fn optional<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(|value| value.to_string()).unwrap_or_default()
+}
+
+// display names are free-form, so anything could be in them
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
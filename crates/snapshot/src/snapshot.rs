@@ -0,0 +1,485 @@
+use std::collections::{HashMap, HashSet};
+use anchor_lang::{AccountDeserialize, AnchorDeserialize, Discriminator};
+use anchor_lang::prelude::Pubkey;
+use keychain::account::{CurrentKeyChain, KeyChainActionType, KeyChainKey, KeyChainState, KeyChainV1, PendingKeyChainAction};
+use keychain::constant::{CURRENT_KEYCHAIN_VERSION, CURRENT_KEY_VERSION, KEYCHAIN, KEYCHAIN_STATE_SPACE, KEY_SPACE, MAX_DOMAIN_KEYS};
+use serde::Serialize;
+use crate::source::RawAccount;
+
+#[derive(Serialize)]
+pub struct Snapshot {
+    pub domain: String,
+    pub keychains: Vec<KeychainRecord>,
+    pub keys: Vec<KeyRecord>,
+    pub v1_keychains: Vec<V1KeychainRecord>,
+    pub issues: Vec<Issue>,
+}
+
+#[derive(Serialize)]
+pub struct KeychainRecord {
+    pub address: String,
+    pub name: String,
+    pub num_keys: u16,
+    pub keys: Vec<KeychainKeyRecord>,
+    // the rest come from the keychain state (if it was found + could be decoded)
+    pub state: Option<String>,
+    pub version: Option<u8>,
+    pub action_threshold: Option<u8>,
+    pub display_name: Option<String>,
+    pub pending_action: Option<String>,
+    pub beneficiary: Option<String>,
+    pub last_active: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct KeychainKeyRecord {
+    pub key: String,
+    pub weight: u8,
+    pub program_controlled: bool,
+}
+
+#[derive(Serialize)]
+pub struct KeyRecord {
+    pub address: String,
+    pub key: String,
+    pub keychain: String,
+}
+
+#[derive(Serialize)]
+pub struct V1KeychainRecord {
+    pub address: String,
+    pub num_keys: u16,
+    pub keys: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct Issue {
+    pub kind: &'static str,
+    pub account: String,
+    pub detail: String,
+}
+
+// every keychain layout starts with the name, num_keys + domain, so old keychains can be matched to their domain even if
+// they can't be decoded
+fn keychain_domain(data: &[u8]) -> Option<String> {
+    let mut reader = &data[8..];
+    String::deserialize(&mut reader).ok()?;
+    u16::deserialize(&mut reader).ok()?;
+    String::deserialize(&mut reader).ok()
+}
+
+// v1 keys were (key, verified) - KeyChainV1 in the program uses the current UserKey, which has a different layout
+fn decode_v1(address: &Pubkey, data: &[u8], domain: &str) -> Option<V1KeychainRecord> {
+    let mut reader = &data[8..];
+    let num_keys = u16::deserialize(&mut reader).ok()?;
+    if String::deserialize(&mut reader).ok()? != domain {
+        return None;
+    }
+    let keys: Vec<(Pubkey, bool)> = Vec::deserialize(&mut reader).ok()?;
+    Some(V1KeychainRecord { address: address.to_string(), num_keys, keys: keys.iter().map(|(key, _)| key.to_string()).collect() })
+}
+
+// key accounts created before they were versioned are just the keychain + key (and may be too small for the rest)
+fn decode_key(data: &[u8]) -> Option<(KeyChainKey, bool)> {
+    if let Ok(key) = KeyChainKey::try_deserialize(&mut &data[..]) {
+        let current = key.version == CURRENT_KEY_VERSION;
+        return Some((key, current));
+    }
+    let mut reader = &data[8..];
+    let keychain = Pubkey::deserialize(&mut reader).ok()?;
+    let key = Pubkey::deserialize(&mut reader).ok()?;
+    Some((KeyChainKey { keychain, key, bump: 0, version: 0 }, false))
+}
+
+fn has_discriminator(data: &[u8], discriminator: [u8; 8]) -> bool {
+    data.len() >= 8 && data[..8] == discriminator
+}
+
+pub fn key_address(wallet: &Pubkey, domain: &str, program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[wallet.as_ref(), KEY_SPACE.as_bytes(), domain.as_bytes(), KEYCHAIN.as_bytes()], program_id).0
+}
+
+pub fn state_address(keychain: &Pubkey, domain: &str, program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[keychain.as_ref(), KEYCHAIN_STATE_SPACE.as_bytes(), domain.as_bytes(), KEYCHAIN.as_bytes()], program_id).0
+}
+
+// decodes the domain's accounts out of all of the program's accounts and cross-checks them
+pub fn build(domain: &str, program_id: &Pubkey, accounts: &[RawAccount]) -> Snapshot {
+    let mut issues = vec![];
+    let mut issue = |kind: &'static str, account: &Pubkey, detail: String| issues.push(Issue { kind, account: account.to_string(), detail });
+
+    let mut keychains: HashMap<Pubkey, CurrentKeyChain> = HashMap::new();
+    let mut states: HashMap<Pubkey, KeyChainState> = HashMap::new();
+    let mut keys: HashMap<Pubkey, (Pubkey, KeyChainKey)> = HashMap::new();
+    let mut v1_keychains = vec![];
+
+    for account in accounts.iter().filter(|account| account.owner == *program_id) {
+        let data = account.data.as_slice();
+        if has_discriminator(data, CurrentKeyChain::discriminator()) {
+            if keychain_domain(data).as_deref() != Some(domain) {
+                continue;
+            }
+            match CurrentKeyChain::try_deserialize(&mut &data[..]) {
+                Ok(keychain) => { keychains.insert(account.address, keychain); },
+                // older keychain layouts share the discriminator - they get rewritten by upgrade_keychain
+                Err(err) => issue("undecodable_keychain", &account.address, err.to_string()),
+            }
+        } else if has_discriminator(data, KeyChainState::discriminator()) {
+            // states don't store the domain, so it's checked against the state pda
+            let keychain = data.get(9..41).and_then(|bytes| Pubkey::try_from(bytes).ok());
+            if !matches!(keychain, Some(keychain) if state_address(&keychain, domain, program_id) == account.address) {
+                continue;
+            }
+            match KeyChainState::try_deserialize(&mut &data[..]) {
+                Ok(state) => { states.insert(account.address, state); },
+                Err(_) => issue("outdated_state", &account.address, format!("version {} can't be decoded - needs upgrade_keychain", data[8])),
+            }
+        } else if has_discriminator(data, KeyChainKey::discriminator()) {
+            if let Some((key, current)) = decode_key(data) {
+                if key_address(&key.key, domain, program_id) == account.address {
+                    if !current {
+                        issue("outdated_key_account", &account.address, format!("version {} (current is {}) - needs migrate_key", key.version, CURRENT_KEY_VERSION));
+                    }
+                    keys.insert(key.key, (account.address, key));
+                }
+            }
+        } else if has_discriminator(data, KeyChainV1::discriminator()) {
+            v1_keychains.extend(decode_v1(&account.address, data, domain));
+        }
+    }
+
+    // keychains <-> states
+    let mut keychain_states = HashMap::new();
+    for address in keychains.keys() {
+        let state_address = state_address(address, domain, program_id);
+        match states.get(&state_address) {
+            Some(state) => { keychain_states.insert(*address, (state_address, state)); },
+            None => issue("missing_state", address, format!("no keychain state at {}", state_address)),
+        }
+    }
+    for (address, state) in states.iter() {
+        if !keychains.contains_key(&state.keychain) {
+            issue("orphaned_state", address, format!("keychain {} doesn't exist", state.keychain));
+        }
+    }
+
+    // keys on keychains <-> key accounts (pointers)
+    for (address, keychain) in keychains.iter() {
+        if usize::from(keychain.num_keys) != keychain.keys.len() {
+            issue("num_keys_mismatch", address, format!("num_keys is {} but {} keys are stored", keychain.num_keys, keychain.keys.len()));
+        }
+        let mut seen = HashSet::new();
+        for user_key in keychain.keys.iter() {
+            if !seen.insert(user_key.key) {
+                issue("duplicate_key", address, user_key.key.to_string());
+            }
+            match keys.get(&user_key.key) {
+                None => issue("missing_key_account", address, format!("{} has no key account", user_key.key)),
+                Some((key_address, key)) if key.keychain != *address =>
+                    issue("mismatched_key_account", key_address, format!("{} is on {} but its key account points to {}", user_key.key, address, key.keychain)),
+                _ => {},
+            }
+        }
+    }
+    for (address, key) in keys.values() {
+        let on_keychain = matches!(keychains.get(&key.keychain), Some(keychain) if keychain.has_key(&key.key));
+        // a key that verified before its add passed already has its key account
+        let pending_add = matches!(keychain_states.get(&key.keychain).and_then(|(_, state)| state.pending_action.as_ref()),
+            Some(PendingKeyChainAction { action_type: KeyChainActionType::AddKey, key: pending_key, .. }) if *pending_key == key.key);
+        if !on_keychain && !pending_add {
+            issue("orphaned_key_account", address, format!("{} isn't on keychain {}", key.key, key.keychain));
+        }
+    }
+
+    // stale pending actions + outdated versions
+    for (address, (state_address, state)) in keychain_states.iter() {
+        let keychain = &keychains[address];
+        if state.keychain_version < CURRENT_KEYCHAIN_VERSION {
+            issue("outdated_version", state_address, format!("version {} (current is {})", state.keychain_version, CURRENT_KEYCHAIN_VERSION));
+        }
+        if let Some(pending_action) = &state.pending_action {
+            if let Some(reason) = stale_reason(keychain, pending_action) {
+                issue("stale_pending_action", state_address, format!("{:?} for {}: {}", pending_action.action_type, pending_action.key, reason));
+            }
+        }
+    }
+
+    let mut keychain_records: Vec<KeychainRecord> = keychains.iter().map(|(address, keychain)| {
+        let state = keychain_states.get(address);
+        KeychainRecord {
+            address: address.to_string(),
+            name: keychain.name.clone(),
+            num_keys: keychain.num_keys,
+            keys: keychain.keys.iter().map(|key| KeychainKeyRecord {
+                key: key.key.to_string(),
+                weight: key.weight,
+                program_controlled: key.program_controlled,
+            }).collect(),
+            state: state.map(|(address, _)| address.to_string()),
+            version: state.map(|(_, state)| state.keychain_version),
+            action_threshold: state.map(|(_, state)| state.action_threshold),
+            display_name: state.and_then(|(_, state)| state.display_name.clone()),
+            pending_action: state.and_then(|(_, state)| state.pending_action.as_ref())
+                .map(|action| format!("{:?} {}", action.action_type, action.key)),
+            beneficiary: state.and_then(|(_, state)| state.beneficiary).map(|beneficiary| beneficiary.to_string()),
+            last_active: state.map(|(_, state)| state.last_active),
+        }
+    }).collect();
+    keychain_records.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut key_records: Vec<KeyRecord> = keys.values().map(|(address, key)| KeyRecord {
+        address: address.to_string(),
+        key: key.key.to_string(),
+        keychain: key.keychain.to_string(),
+    }).collect();
+    key_records.sort_by(|a, b| a.keychain.cmp(&b.keychain).then(a.key.cmp(&b.key)));
+
+    issues.sort_by(|a, b| a.kind.cmp(b.kind).then(a.account.cmp(&b.account)));
+
+    Snapshot { domain: domain.to_string(), keychains: keychain_records, keys: key_records, v1_keychains, issues }
+}
+
+// a pending action that can't go through anymore (and blocks the keychain until it's rejected)
+fn stale_reason(keychain: &CurrentKeyChain, pending_action: &PendingKeyChainAction) -> Option<&'static str> {
+    if (keychain.keys.len()..MAX_DOMAIN_KEYS).any(|index| pending_action.votes.is_set(index as u8)) {
+        return Some("votes from keys that aren't on the keychain");
+    }
+    match pending_action.action_type {
+        KeyChainActionType::AddKey if keychain.has_key(&pending_action.key) => Some("key is already on the keychain"),
+        KeyChainActionType::RemoveKey | KeyChainActionType::SetWeight { .. } | KeyChainActionType::Detach { .. }
+            if !keychain.has_key(&pending_action.key) => Some("key isn't on the keychain"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anchor_lang::{AccountSerialize, AnchorSerialize};
+    use keychain::account::{KeyChainStateV3, KeyChainV7, UserKey, UserKeyV7};
+    use super::*;
+
+    const DOMAIN: &str = "testdomain";
+
+    fn raw(address: Pubkey, data: Vec<u8>) -> RawAccount {
+        RawAccount { address, owner: keychain::ID, data }
+    }
+
+    fn serialized<T: AccountSerialize>(account: &T) -> Vec<u8> {
+        let mut data = vec![];
+        account.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    // for older layouts, which share the current account's discriminator
+    fn serialized_as<T: AnchorSerialize>(discriminator: [u8; 8], account: &T) -> Vec<u8> {
+        [&discriminator[..], &account.try_to_vec().unwrap()].concat()
+    }
+
+    fn keychain(keys: &[Pubkey]) -> CurrentKeyChain {
+        CurrentKeyChain {
+            name: "player".to_string(),
+            num_keys: keys.len() as u16,
+            domain: DOMAIN.to_string(),
+            bump: 255,
+            keys: keys.iter().map(|key| UserKey::new(*key)).collect(),
+        }
+    }
+
+    fn state(keychain: Pubkey, pending_action: Option<PendingKeyChainAction>) -> RawAccount {
+        raw(state_address(&keychain, DOMAIN, &keychain::ID), serialized(&KeyChainState {
+            keychain_version: CURRENT_KEYCHAIN_VERSION,
+            keychain,
+            pending_action,
+            action_threshold: 1,
+            display_name: None,
+            beneficiary: None,
+            inactivity_period: 0,
+            last_active: 0,
+            nonce: 0,
+            identity_holder: None,
+            bump: 255,
+            has_activity_log: false,
+        }))
+    }
+
+    fn key_account(key: Pubkey, keychain: Pubkey, version: u8) -> RawAccount {
+        raw(key_address(&key, DOMAIN, &keychain::ID), serialized(&KeyChainKey { keychain, key, bump: 255, version }))
+    }
+
+    // a consistent keychain: its state + a key account for each key
+    fn fixture(keys: &[Pubkey]) -> (Pubkey, Vec<RawAccount>) {
+        let address = Pubkey::new_unique();
+        let mut accounts = vec![raw(address, serialized(&keychain(keys))), state(address, None)];
+        accounts.extend(keys.iter().map(|key| key_account(*key, address, CURRENT_KEY_VERSION)));
+        (address, accounts)
+    }
+
+    fn issues(accounts: &[RawAccount]) -> Vec<(&'static str, String)> {
+        build(DOMAIN, &keychain::ID, accounts).issues.into_iter().map(|issue| (issue.kind, issue.account)).collect()
+    }
+
+    #[test]
+    fn consistent_keychains_have_no_issues() {
+        let (address, mut accounts) = fixture(&[Pubkey::new_unique(), Pubkey::new_unique()]);
+        accounts.extend(fixture(&[Pubkey::new_unique()]).1);
+        // accounts from other programs + domains are skipped
+        accounts.push(RawAccount { address: Pubkey::new_unique(), owner: Pubkey::new_unique(), data: serialized(&keychain(&[])) });
+        let mut other_domain = keychain(&[Pubkey::new_unique()]);
+        other_domain.domain = "otherdomain".to_string();
+        accounts.push(raw(Pubkey::new_unique(), serialized(&other_domain)));
+
+        let snapshot = build(DOMAIN, &keychain::ID, &accounts);
+        assert!(snapshot.issues.is_empty());
+        assert_eq!(snapshot.keychains.len(), 2);
+        assert_eq!(snapshot.keys.len(), 3);
+        let record = snapshot.keychains.iter().find(|record| record.address == address.to_string()).unwrap();
+        assert_eq!(record.keys.len(), 2);
+        assert_eq!(record.state, Some(state_address(&address, DOMAIN, &keychain::ID).to_string()));
+        assert_eq!(record.version, Some(CURRENT_KEYCHAIN_VERSION));
+    }
+
+    #[test]
+    fn reports_missing_and_orphaned_states() {
+        let (address, mut accounts) = fixture(&[Pubkey::new_unique()]);
+        let state = accounts.remove(1);
+        let orphaned = Pubkey::new_unique();
+        accounts.push(self::state(orphaned, None));
+
+        assert_eq!(issues(&accounts), vec![
+            ("missing_state", address.to_string()),
+            ("orphaned_state", state_address(&orphaned, DOMAIN, &keychain::ID).to_string()),
+        ]);
+        // a state that isn't at its keychain's state pda isn't this domain's
+        accounts.push(RawAccount { address: Pubkey::new_unique(), ..state });
+        assert_eq!(issues(&accounts).len(), 2);
+    }
+
+    #[test]
+    fn reports_keys_that_dont_match_their_key_accounts() {
+        let (wallet, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (address, mut accounts) = fixture(&[wallet, other]);
+        let (other_address, other_accounts) = fixture(&[Pubkey::new_unique()]);
+        accounts.extend(other_accounts);
+
+        // the wallet's key account points at a keychain it isn't on, and the other key has none
+        accounts.retain(|account| account.address != key_address(&wallet, DOMAIN, &keychain::ID) && account.address != key_address(&other, DOMAIN, &keychain::ID));
+        accounts.push(key_account(wallet, other_address, CURRENT_KEY_VERSION));
+        let wallet_key = key_address(&wallet, DOMAIN, &keychain::ID).to_string();
+
+        assert_eq!(issues(&accounts), vec![
+            ("mismatched_key_account", wallet_key.clone()),
+            ("missing_key_account", address.to_string()),
+            ("orphaned_key_account", wallet_key),
+        ]);
+    }
+
+    #[test]
+    fn reports_duplicate_keys_and_key_counts() {
+        let wallet = Pubkey::new_unique();
+        let (address, mut accounts) = fixture(&[wallet]);
+        let mut duplicated = keychain(&[wallet, wallet]);
+        duplicated.num_keys = 3;
+        accounts[0] = raw(address, serialized(&duplicated));
+
+        assert_eq!(issues(&accounts), vec![
+            ("duplicate_key", address.to_string()),
+            ("num_keys_mismatch", address.to_string()),
+        ]);
+    }
+
+    #[test]
+    fn a_pending_add_keeps_its_key_account() {
+        let pending = Pubkey::new_unique();
+        let (address, mut accounts) = fixture(&[Pubkey::new_unique()]);
+        accounts.push(key_account(pending, address, CURRENT_KEY_VERSION));
+        assert_eq!(issues(&accounts), vec![("orphaned_key_account", key_address(&pending, DOMAIN, &keychain::ID).to_string())]);
+
+        let mut pending_action = PendingKeyChainAction::new(KeyChainActionType::AddKey, pending);
+        pending_action.verify();
+        accounts[1] = state(address, Some(pending_action));
+        assert!(issues(&accounts).is_empty());
+    }
+
+    #[test]
+    fn reports_stale_pending_actions() {
+        let wallet = Pubkey::new_unique();
+        let (address, mut accounts) = fixture(&[wallet]);
+        let state_address = state_address(&address, DOMAIN, &keychain::ID).to_string();
+        let stale = |action_type, key, voter: u8| {
+            let mut pending_action = PendingKeyChainAction::new(action_type, key);
+            pending_action.vote(voter, true);
+            Some(pending_action)
+        };
+
+        // still possible
+        accounts[1] = state(address, stale(KeyChainActionType::RemoveKey, wallet, 0));
+        assert!(issues(&accounts).is_empty());
+
+        for pending_action in [
+            stale(KeyChainActionType::AddKey, wallet, 0),
+            stale(KeyChainActionType::RemoveKey, Pubkey::new_unique(), 0),
+            stale(KeyChainActionType::SetWeight { weight: 2 }, Pubkey::new_unique(), 0),
+            stale(KeyChainActionType::Detach { name: "detached".to_string() }, Pubkey::new_unique(), 0),
+            // voted on by a key that's since been removed
+            stale(KeyChainActionType::SetThreshold { threshold: 1 }, address, 1),
+        ] {
+            accounts[1] = state(address, pending_action);
+            assert_eq!(issues(&accounts), vec![("stale_pending_action", state_address.clone())]);
+        }
+    }
+
+    #[test]
+    fn reports_accounts_that_need_upgrading() {
+        let wallet = Pubkey::new_unique();
+        let (address, mut accounts) = fixture(&[wallet]);
+        let state_address = state_address(&address, DOMAIN, &keychain::ID);
+        let key_address = key_address(&wallet, DOMAIN, &keychain::ID);
+
+        // a decodable state that's behind, and a key account from before versions
+        let mut state = KeyChainState::try_deserialize(&mut &accounts[1].data[..]).unwrap();
+        state.keychain_version = CURRENT_KEYCHAIN_VERSION - 1;
+        accounts[1] = raw(state_address, serialized(&state));
+        accounts[2] = key_account(wallet, address, 0);
+        assert_eq!(issues(&accounts), vec![
+            ("outdated_key_account", key_address.to_string()),
+            ("outdated_version", state_address.to_string()),
+        ]);
+
+        // an unversioned key account is just the keychain + key, and old states + keychains can't be decoded at all
+        accounts[2] = raw(key_address, [&KeyChainKey::discriminator()[..], address.as_ref(), wallet.as_ref()].concat());
+        accounts[1] = raw(state_address, serialized_as(KeyChainState::discriminator(), &KeyChainStateV3 {
+            keychain_version: 3,
+            keychain: address,
+            pending_action: None,
+            action_threshold: 1,
+        }));
+        accounts[0] = raw(address, serialized_as(CurrentKeyChain::discriminator(), &KeyChainV7 {
+            name: "player".to_string(),
+            num_keys: 1,
+            domain: DOMAIN.to_string(),
+            bump: 255,
+            keys: vec![UserKeyV7 { key: wallet, weight: 1 }],
+        }));
+        let snapshot = build(DOMAIN, &keychain::ID, &accounts);
+        assert_eq!(snapshot.keys.len(), 1);
+        let kinds: Vec<&str> = snapshot.issues.iter().map(|issue| issue.kind).collect();
+        // the key account looks orphaned since its keychain couldn't be decoded
+        assert_eq!(kinds, vec!["orphaned_key_account", "outdated_key_account", "outdated_state", "undecodable_keychain"]);
+    }
+
+    #[test]
+    fn decodes_v1_keychains() {
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let v1 = |domain: &str| serialized_as(KeyChainV1::discriminator(), &(2u16, domain.to_string(), keys.map(|key| (key, true)).to_vec()));
+        let address = Pubkey::new_unique();
+        let accounts = [raw(address, v1(DOMAIN)), raw(Pubkey::new_unique(), v1("otherdomain"))];
+
+        let snapshot = build(DOMAIN, &keychain::ID, &accounts);
+        assert_eq!(snapshot.v1_keychains.len(), 1);
+        let record = &snapshot.v1_keychains[0];
+        assert_eq!(record.address, address.to_string());
+        assert_eq!(record.num_keys, 2);
+        assert_eq!(record.keys, keys.map(|key| key.to_string()).to_vec());
+        assert!(snapshot.issues.is_empty());
+    }
+}
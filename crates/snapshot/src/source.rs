@@ -0,0 +1,87 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use anchor_lang::prelude::Pubkey;
+use serde::Deserialize;
+use crate::Result;
+
+pub struct RawAccount {
+    pub address: Pubkey,
+    pub owner: Pubkey,
+    pub data: Vec<u8>,
+}
+
+// the json the solana cli writes (`solana account <address> --output json`) and getProgramAccounts returns
+#[derive(Deserialize)]
+struct KeyedAccountJson {
+    pubkey: String,
+    account: AccountJson,
+}
+
+#[derive(Deserialize)]
+struct AccountJson {
+    owner: String,
+    // [data, encoding]
+    data: (String, String),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DumpFile {
+    One(KeyedAccountJson),
+    Many(Vec<KeyedAccountJson>),
+}
+
+impl TryFrom<KeyedAccountJson> for RawAccount {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(keyed: KeyedAccountJson) -> Result<Self> {
+        let (data, encoding) = keyed.account.data;
+        if encoding != "base64" {
+            return Err(format!("{}: unsupported encoding {}", keyed.pubkey, encoding).into());
+        }
+        Ok(Self {
+            address: Pubkey::from_str(&keyed.pubkey)?,
+            owner: Pubkey::from_str(&keyed.account.owner)?,
+            data: base64::decode(data)?,
+        })
+    }
+}
+
+// reads every .json file in a dump directory - each holds one account, or a list of them (a saved getProgramAccounts
+// result)
+pub fn load_dir(dir: &Path) -> Result<Vec<RawAccount>> {
+    let mut accounts = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new("json")) {
+            continue;
+        }
+        let file: DumpFile = serde_json::from_slice(&fs::read(&path)?)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        match file {
+            DumpFile::One(account) => accounts.push(account.try_into()?),
+            DumpFile::Many(list) => {
+                for account in list {
+                    accounts.push(account.try_into()?);
+                }
+            }
+        }
+    }
+    Ok(accounts)
+}
+
+#[cfg(feature = "rpc")]
+pub fn load_rpc(url: &str, program_id: &Pubkey) -> Result<Vec<RawAccount>> {
+    let client = solana_client::rpc_client::RpcClient::new(url.to_string());
+    Ok(client.get_program_accounts(program_id)?
+        .into_iter()
+        .map(|(address, account)| RawAccount { address, owner: account.owner, data: account.data })
+        .collect())
+}
+
+#[cfg(not(feature = "rpc"))]
+pub fn load_rpc(_url: &str, _program_id: &Pubkey) -> Result<Vec<RawAccount>> {
+    Err("built without the rpc feature - use --dir".into())
+}
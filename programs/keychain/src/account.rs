@@ -1,10 +1,25 @@
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
 use anchor_lang::prelude::*;
-use crate::constant::MAX_KEYS;
+use anchor_lang::Discriminator;
+use anchor_lang::solana_program::instruction::Instruction;
+use crate::constant::{ACTIVITY_LOG_SIZE, CURRENT_DOMAIN_VERSION, CURRENT_KEY_VERSION, DEFAULT_KEY_WEIGHT, MAX_DISPLAY_NAME_LENGTH, MAX_KEYS, MAX_NAME_LENGTH, MIN_NAME_LENGTH};
+use crate::error::KeychainError;
 
 // represents a user's wallet - previously stored a verified field, but was moved to keychain state
 #[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize)]
 pub struct UserKey {
     pub key: Pubkey,
+    // how much this key's vote counts towards the action threshold
+    pub weight: u8,
+    // a pda (off-curve) key that its owning program signs for with invoke_signed, rather than a wallet
+    pub program_controlled: bool,
+}
+
+impl UserKey {
+    pub fn new(key: Pubkey) -> Self {
+        Self { key, weight: DEFAULT_KEY_WEIGHT, program_controlled: !key.is_on_curve() }
+    }
 }
 
 // the current version of the keychain
@@ -24,8 +39,24 @@ impl CurrentKeyChain {
             2 +     // num_keys
             32 +    // domain
             1 +     // bump
-            (4 + (MAX_KEYS * 32)) +   // keys
-            192;     // extra space
+            (4 + (MAX_KEYS * 34)) +   // keys
+            187;     // extra space
+
+    // wallet keychains are named after the wallet's address, which is longer than any name that can be used as a seed
+    pub fn is_wallet_keychain(&self) -> bool {
+        self.name.len() > MAX_NAME_LENGTH
+    }
+
+    // the seed a keychain name contributes to the keychain pda (and pdas derived from it, like yardsale listings):
+    // the name itself, or the wallet's bytes for wallet keychains
+    pub fn name_seed(name: &str) -> Vec<u8> {
+        if name.len() > MAX_NAME_LENGTH {
+            if let Ok(wallet) = Pubkey::from_str(name) {
+                return wallet.to_bytes().to_vec();
+            }
+        }
+        name.as_bytes().to_vec()
+    }
 
     pub fn has_key(&self, key: &Pubkey) -> bool {
         for k in self.keys.iter() {
@@ -55,16 +86,241 @@ impl CurrentKeyChain {
     }
 
     pub fn add_key(&mut self, key: Pubkey) {
-        self.keys.push(UserKey { key });
+        self.keys.push(UserKey::new(key));
         self.num_keys += 1;
     }
 
+    // moves in another keychain's keys (keeping their weights)
+    pub fn merge_keys(&mut self, keys: &[UserKey]) {
+        self.keys.extend_from_slice(keys);
+        self.num_keys += keys.len() as u16;
+    }
+
     pub fn remove_key(&mut self, key: Pubkey) {
         let key_index = self.index_of(&key).unwrap();
         self.keys.swap_remove(key_index);
         self.num_keys -= 1;
     }
 
+    // total weight of the keys that have voted
+    pub fn vote_weight(&self, votes: &SmallBitSet) -> u16 {
+        self.keys.iter().enumerate()
+            .filter(|(i, _)| votes.is_set(*i as u8))
+            .map(|(_, k)| u16::from(k.weight))
+            .sum()
+    }
+
+    // either the votes carry enough weight (threshold 0 = all keys) or all keys have voted
+    pub fn threshold_reached(&self, votes: &SmallBitSet, action_threshold: u8) -> bool {
+        (action_threshold > 0 && self.vote_weight(votes) >= u16::from(action_threshold)) ||
+            u16::from(votes.count_set()) == self.num_keys
+    }
+
+    // a removal is decided by the remaining keys: the removed key's vote doesn't count and the threshold is capped to the
+    // weight the remaining keys carry, so a compromised key can't hold up its own removal
+    pub fn removal_threshold_reached(&self, votes: &SmallBitSet, action_threshold: u8, removed: &Pubkey) -> bool {
+        let mut votes = votes.clone();
+        let mut remaining_weight: u16 = self.keys.iter().map(|k| u16::from(k.weight)).sum();
+        if let Some(index) = self.index_of(removed) {
+            votes.unset_index(index as u8);
+            remaining_weight -= u16::from(self.keys[index].weight);
+        }
+        let threshold = u16::from(action_threshold).min(remaining_weight);
+        (threshold > 0 && self.vote_weight(&votes) >= threshold) ||
+            u16::from(votes.count_set()) == self.num_keys - 1
+    }
+
+    // 0 = any single rejection (veto) cancels, otherwise the rejections need to reach the threshold (or every key rejects)
+    pub fn rejection_reached(&self, rejections: &SmallBitSet, rejection_threshold: u8) -> bool {
+        if rejection_threshold == 0 {
+            return rejections.count_set() > 0;
+        }
+        self.threshold_reached(rejections, rejection_threshold)
+    }
+
+}
+
+// v2 layout (before key weights), only used to upgrade existing keychains. this was stored with the CurrentKeyChain
+// discriminator, so it gets deserialized manually
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct KeyChainV2 {
+    pub name: String,
+    pub num_keys: u16,
+    pub domain: String,
+    pub bump: u8,
+    pub keys: Vec<Pubkey>,
+}
+
+// v3 - v7 layout (before program-controlled keys), only used to upgrade existing keychains
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct KeyChainV7 {
+    pub name: String,
+    pub num_keys: u16,
+    pub domain: String,
+    pub bump: u8,
+    pub keys: Vec<UserKeyV7>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct UserKeyV7 {
+    pub key: Pubkey,
+    pub weight: u8,
+}
+
+// v3 keychain state (before display names) - read manually when upgrading since the bytes after it may be stale.
+// v4 is the same plus a display name at the end
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct KeyChainStateV3 {
+    pub keychain_version: u8,
+    pub keychain: Pubkey,
+    pub pending_action: Option<PendingKeyChainActionV4>,
+    pub action_threshold: u8,
+}
+
+// pending action before rejections were tracked (up to v4)
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct PendingKeyChainActionV4 {
+    pub action_type: KeyChainActionType,
+    pub key: Pubkey,
+    pub verified: bool,
+    pub votes: SmallBitSet
+}
+
+// v5 keychain state (before inheritance) - same as v4 with rejections in the pending action
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct KeyChainStateV5 {
+    pub keychain_version: u8,
+    pub keychain: Pubkey,
+    pub pending_action: Option<PendingKeyChainAction>,
+    pub action_threshold: u8,
+    pub display_name: Option<String>,
+}
+
+impl KeyChainStateV5 {
+    pub fn from_v3(state: KeyChainStateV3, display_name: Option<String>) -> Self {
+        Self {
+            keychain_version: state.keychain_version,
+            keychain: state.keychain,
+            pending_action: state.pending_action.map(PendingKeyChainAction::from),
+            action_threshold: state.action_threshold,
+            display_name,
+        }
+    }
+}
+
+// v6 keychain state (before intent nonces)
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct KeyChainStateV6 {
+    pub keychain_version: u8,
+    pub keychain: Pubkey,
+    pub pending_action: Option<PendingKeyChainAction>,
+    pub action_threshold: u8,
+    pub display_name: Option<String>,
+    pub beneficiary: Option<Pubkey>,
+    pub inactivity_period: i64,
+    pub last_active: i64,
+}
+
+impl From<KeyChainStateV5> for KeyChainStateV6 {
+    fn from(state: KeyChainStateV5) -> Self {
+        Self {
+            keychain_version: state.keychain_version,
+            keychain: state.keychain,
+            pending_action: state.pending_action,
+            action_threshold: state.action_threshold,
+            display_name: state.display_name,
+            beneficiary: None,
+            inactivity_period: 0,
+            last_active: 0,
+        }
+    }
+}
+
+// v8 keychain state (before identity nfts) - v7 added the nonce, v8 only changed the keychain
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct KeyChainStateV8 {
+    pub keychain_version: u8,
+    pub keychain: Pubkey,
+    pub pending_action: Option<PendingKeyChainAction>,
+    pub action_threshold: u8,
+    pub display_name: Option<String>,
+    pub beneficiary: Option<Pubkey>,
+    pub inactivity_period: i64,
+    pub last_active: i64,
+    pub nonce: u64,
+}
+
+impl From<KeyChainStateV6> for KeyChainStateV8 {
+    fn from(state: KeyChainStateV6) -> Self {
+        Self {
+            keychain_version: state.keychain_version,
+            keychain: state.keychain,
+            pending_action: state.pending_action,
+            action_threshold: state.action_threshold,
+            display_name: state.display_name,
+            beneficiary: state.beneficiary,
+            inactivity_period: state.inactivity_period,
+            last_active: state.last_active,
+            nonce: 0,
+        }
+    }
+}
+
+// v9 keychain state (before the bump was stored)
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct KeyChainStateV9 {
+    pub keychain_version: u8,
+    pub keychain: Pubkey,
+    pub pending_action: Option<PendingKeyChainAction>,
+    pub action_threshold: u8,
+    pub display_name: Option<String>,
+    pub beneficiary: Option<Pubkey>,
+    pub inactivity_period: i64,
+    pub last_active: i64,
+    pub nonce: u64,
+    pub identity_holder: Option<Pubkey>,
+}
+
+impl From<KeyChainStateV8> for KeyChainStateV9 {
+    fn from(state: KeyChainStateV8) -> Self {
+        Self {
+            keychain_version: state.keychain_version,
+            keychain: state.keychain,
+            pending_action: state.pending_action,
+            action_threshold: state.action_threshold,
+            display_name: state.display_name,
+            beneficiary: state.beneficiary,
+            inactivity_period: state.inactivity_period,
+            last_active: state.last_active,
+            nonce: state.nonce,
+            identity_holder: None,
+        }
+    }
+}
+
+impl KeyChainState {
+    pub fn from_v9(state: KeyChainStateV9, bump: u8) -> Self {
+        Self {
+            keychain_version: state.keychain_version,
+            keychain: state.keychain,
+            pending_action: state.pending_action,
+            action_threshold: state.action_threshold,
+            display_name: state.display_name,
+            beneficiary: state.beneficiary,
+            inactivity_period: state.inactivity_period,
+            last_active: state.last_active,
+            nonce: state.nonce,
+            identity_holder: state.identity_holder,
+            bump,
+            has_activity_log: false,
+        }
+    }
+}
+
+impl From<PendingKeyChainActionV4> for PendingKeyChainAction {
+    fn from(action: PendingKeyChainActionV4) -> Self {
+        Self { action_type: action.action_type, key: action.key, verified: action.verified, votes: action.votes, rejections: SmallBitSet::new() }
+    }
 }
 
 // older versions
@@ -86,13 +342,89 @@ pub struct KeyChainKey {
     pub keychain: Pubkey,
     // the key/wallet this key holds - matches the one in the keychain
     pub key: Pubkey,
+    pub bump: u8,
+    // added after the bump, so older key accounts read 0 here (or are too small for it)
+    pub version: u8,
 }
 
 impl KeyChainKey {
-    pub const MAX_SIZE: usize =
+    // key accounts created on verify don't get the extra space
+    pub const SIZE: usize =
             32 +    // keychain
             32 +    // key
-            192;     // extra space in case we need to store more data later;
+            1 +     // bump
+            1;      // version
+
+    pub const MAX_SIZE: usize =
+            KeyChainKey::SIZE +
+            190;     // extra space in case we need to store more data later;
+
+    // offset of the version in the account data
+    pub const VERSION_OFFSET: usize = 8 + 32 + 32 + 1;
+
+    // older key accounts need migrate_key first
+    pub fn check_version(&self) -> Result<()> {
+        require!(self.version == CURRENT_KEY_VERSION, KeychainError::InvalidKeychainVersion);
+        Ok(())
+    }
+
+    // the stored bump, as long as the key account is on the current version (older ones don't store it)
+    pub fn checked_bump(&self) -> Result<u8> {
+        self.check_version()?;
+        Ok(self.bump)
+    }
+}
+
+// a key account taken by instructions that need it on the current version. v0 key accounts created on verify (without
+// the extra space) are too small to deserialize as a KeyChainKey, so they're rejected as an old version here rather than
+// failing to deserialize
+#[derive(Clone)]
+pub struct CurrentKeyChainKey(KeyChainKey);
+
+impl AccountDeserialize for CurrentKeyChainKey {
+    fn try_deserialize(buf: &mut &[u8]) -> Result<Self> {
+        if buf.len() < 8 + KeyChainKey::SIZE && buf.starts_with(&KeyChainKey::discriminator()) {
+            return err!(KeychainError::InvalidKeychainVersion);
+        }
+        KeyChainKey::try_deserialize(buf).map(Self)
+    }
+
+    fn try_deserialize_unchecked(buf: &mut &[u8]) -> Result<Self> {
+        KeyChainKey::try_deserialize_unchecked(buf).map(Self)
+    }
+}
+
+impl AccountSerialize for CurrentKeyChainKey {
+    fn try_serialize<W: std::io::Write>(&self, writer: &mut W) -> Result<()> {
+        self.0.try_serialize(writer)
+    }
+}
+
+impl Owner for CurrentKeyChainKey {
+    fn owner() -> Pubkey {
+        KeyChainKey::owner()
+    }
+}
+
+impl Deref for CurrentKeyChainKey {
+    type Target = KeyChainKey;
+
+    fn deref(&self) -> &KeyChainKey {
+        &self.0
+    }
+}
+
+impl DerefMut for CurrentKeyChainKey {
+    fn deref_mut(&mut self) -> &mut KeyChainKey {
+        &mut self.0
+    }
+}
+
+// v0 key account (before the bump + version were stored)
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct KeyChainKeyV0 {
+    pub keychain: Pubkey,
+    pub key: Pubkey,
 }
 
 #[account]
@@ -104,7 +436,14 @@ pub struct CurrentDomain {
     pub bump: u8,
     // params
     pub key_cost: u64,            // the cost to add a key to a keychain
-    pub keychain_action_threshold: u8,            // the number of keys required to verify a new key (0 = all keys)
+    pub keychain_action_threshold: u8,            // the key weight required to verify a new key (0 = all keys)
+    // keychain rules - these were added later, so 0 means the default (older domains read 0s from the extra storage)
+    pub max_keys: u8,
+    pub min_name_len: u8,
+    pub max_name_len: u8,
+    pub name_charset: NameCharset,
+    // key weight needed to reject (cancel) a pending action. 0 = a single veto cancels
+    pub rejection_threshold: u8,
 }
 
 impl CurrentDomain {
@@ -116,8 +455,126 @@ impl CurrentDomain {
             1 +     // bump
             1 +
             1 +     // threshold
-            192;  // extra storage
+            1 +     // max_keys
+            1 +     // min_name_len
+            1 +     // max_name_len
+            1 +     // name_charset
+            1 +     // rejection_threshold
+            187;  // extra storage
+
+    pub fn key_limit(&self) -> usize {
+        if self.max_keys == 0 { MAX_KEYS } else { usize::from(self.max_keys) }
+    }
+
+    pub fn min_name_length(&self) -> usize {
+        if self.min_name_len == 0 { MIN_NAME_LENGTH } else { usize::from(self.min_name_len) }
+    }
+
+    pub fn max_name_length(&self) -> usize {
+        if self.max_name_len == 0 { MAX_NAME_LENGTH } else { usize::from(self.max_name_len) }
+    }
 
+    // checks a keychain name against the domain's rules
+    pub fn check_name(&self, name: &str) -> Result<()> {
+        require!(name.as_bytes().len() <= self.max_name_length(), KeychainError::NameTooLong);
+        require!(name.len() >= self.min_name_length(), KeychainError::NameTooShort);
+        require!(name.chars().all(|c| self.name_charset.allows(c)), KeychainError::InvalidName);
+        Ok(())
+    }
+}
+
+// the characters a domain allows in keychain names
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NameCharset {
+    // lowercase letters, digits, '-' and '_'
+    Lowercase,
+    // upper or lowercase letters, digits, '-' and '_'
+    MixedCase,
+    // any printable ascii (no whitespace)
+    Printable,
+}
+
+impl NameCharset {
+    pub fn allows(&self, c: char) -> bool {
+        match self {
+            NameCharset::Lowercase => c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_',
+            NameCharset::MixedCase => c.is_ascii_alphanumeric() || c == '-' || c == '_',
+            NameCharset::Printable => c.is_ascii_graphic(),
+        }
+    }
+}
+
+// a wallet blocklisted by the domain (compromised, sanctioned, etc.) - it can't be linked while this exists
+#[account]
+pub struct BlockedKey {
+    pub domain: Pubkey,
+    pub key: Pubkey,
+}
+
+impl BlockedKey {
+    pub const MAX_SIZE: usize = 32 + 32;
+}
+
+// singleton list of every domain, so clients can discover them without knowing the names. grows (realloc) with each domain
+#[account]
+pub struct DomainRegistry {
+    pub bump: u8,
+    pub domains: Vec<Pubkey>,
+}
+
+impl DomainRegistry {
+    pub fn size(num_domains: usize) -> usize {
+        1 +                     // bump
+        4 + (num_domains * 32)  // domains
+    }
+}
+
+// singleton protocol-wide settings, only updatable by the upgrade authority
+#[account]
+pub struct ProtocolConfig {
+    pub bump: u8,
+    // charged (in lamports) to create a domain, paid to the treasury
+    pub domain_creation_fee: u64,
+    pub treasury: Pubkey,
+    // if not empty, only these wallets can create domains (without an approval)
+    pub allowlist: Vec<Pubkey>,
+    // names that can't be claimed (without an approval)
+    pub reserved_names: Vec<String>,
+    // whether an approval lets a reserved name be claimed - if false, reserved names can't be claimed at all
+    pub allow_reserved_with_approval: bool,
+}
+
+impl ProtocolConfig {
+    pub fn size(allowlist: &[Pubkey], reserved_names: &[String]) -> usize {
+        1 +                                                             // bump
+        8 +                                                             // domain_creation_fee
+        32 +                                                            // treasury
+        4 + (allowlist.len() * 32) +                                    // allowlist
+        4 + reserved_names.iter().map(|n| 4 + n.len()).sum::<usize>() + // reserved_names
+        1                                                               // allow_reserved_with_approval
+    }
+
+    pub fn is_reserved(&self, name: &str) -> bool {
+        self.reserved_names.iter().any(|n| n == name)
+    }
+
+    pub fn is_allowed(&self, creator: &Pubkey) -> bool {
+        self.allowlist.is_empty() || self.allowlist.contains(creator)
+    }
+}
+
+// protocol sign-off for a given wallet to create a domain with a given (reserved) name. closed when the domain is created
+#[account]
+pub struct DomainApproval {
+    pub name: String,
+    // the wallet allowed to create the domain
+    pub authority: Pubkey,
+}
+
+impl DomainApproval {
+    pub const MAX_SIZE: usize =
+            4 + 32 +    // name
+            32;         // authority
 }
 
 ////// these accounts are for versioning - they shouldn't change
@@ -126,6 +583,27 @@ impl CurrentDomain {
 pub enum KeyChainActionType {
     AddKey,
     RemoveKey,
+    // withdraw from the keychain's vault: mint = None for sol. the pending action's key is the destination
+    Withdraw { mint: Option<Pubkey>, amount: u64 },
+    // invoke the instruction stored in a KeyChainProposal. the pending action's key is the proposal
+    Execute,
+    // change the vote weight of the pending action's key
+    SetWeight { weight: u8 },
+    // change the keychain's action threshold (total weight)
+    SetThreshold { threshold: u8 },
+    // merge this keychain into the pending action's key (into = true) or the pending action's key into this one. once
+    // approved (verified), the merge gets executed by merge_keychains when the other keychain has approved too
+    Merge { into: bool },
+    // move the pending action's key to a new keychain with the given name. once approved (verified), detach_key
+    // creates the new keychain
+    Detach { name: String },
+    // set (or clear) the wallet that can claim the keychain after the given inactivity period (in seconds)
+    SetBeneficiary { beneficiary: Option<Pubkey>, inactivity_period: i64 },
+}
+
+impl KeyChainActionType {
+    // largest variant (withdraw): 1 enum + 33 mint + 8 amount (detach: 1 enum + 4 + 32 name, set beneficiary: 1 enum + 33 + 8)
+    pub const MAX_SIZE: usize = 1 + 33 + 8;
 }
 
 // this stores the versioning info AND pending actions, and could possibly be used to store settings or other data in the future
@@ -135,7 +613,23 @@ pub struct KeyChainState {
     // the keychain this account is for
     pub keychain: Pubkey,
     pub pending_action: Option<PendingKeyChainAction>,
-    pub action_threshold: u8
+    // total key weight needed to approve a pending action (0 = all keys)
+    pub action_threshold: u8,
+    // free-form name for display only (unicode, mixed case) - the keychain name stays the unique handle
+    pub display_name: Option<String>,
+    // wallet that can claim the keychain (get its key added) once the keychain's been inactive for the inactivity period
+    pub beneficiary: Option<Pubkey>,
+    // seconds without activity before the beneficiary can claim the keychain
+    pub inactivity_period: i64,
+    // last time a key on the keychain did something (unix timestamp)
+    pub last_active: i64,
+    // the next nonce a signed intent needs to use
+    pub nonce: u64,
+    // the key currently holding the keychain's identity nft (if one's been minted)
+    pub identity_holder: Option<Pubkey>,
+    pub bump: u8,
+    // once the keychain has an activity log, mutating instructions need it passed in (and record to it)
+    pub has_activity_log: bool,
 }
 
 impl KeyChainState {
@@ -143,8 +637,16 @@ impl KeyChainState {
         1 +                 // keychain_version
         32 +                // keychain
         1 +                // action_threshold
-        1 + PendingKeyChainAction::MAX_SIZE       // pending_action
-        + 192;              // extra space
+        1 + PendingKeyChainAction::MAX_SIZE +      // pending_action
+        1 + 4 + MAX_DISPLAY_NAME_LENGTH +           // display_name
+        1 + 32 +            // beneficiary
+        8 +                 // inactivity_period
+        8 +                 // last_active
+        8 +                 // nonce
+        1 + 32 +            // identity_holder
+        1 +                 // bump
+        1                   // has_activity_log
+        + 30;               // extra space
 
     pub fn has_pending_action_type(&self, action_type: KeyChainActionType) -> bool {
         self.pending_action.is_some() && self.pending_action.as_ref().unwrap().action_type == action_type
@@ -158,6 +660,44 @@ impl KeyChainState {
         self.pending_action.is_some() && self.pending_action.as_ref().unwrap().key == *key
     }
 
+    // the other side of a merge with the given keychain, approved by this keychain
+    pub fn has_approved_merge(&self, other: &Pubkey, into: bool) -> bool {
+        match &self.pending_action {
+            Some(pending_action) => pending_action.action_type == KeyChainActionType::Merge { into } &&
+                pending_action.key == *other && pending_action.verified,
+            None => false,
+        }
+    }
+
+    // an approved detach to a keychain with the given name
+    pub fn has_approved_detach(&self, name: &str) -> bool {
+        match &self.pending_action {
+            Some(PendingKeyChainAction { action_type: KeyChainActionType::Detach { name: detach_name }, verified, .. }) =>
+                detach_name == name && *verified,
+            _ => false,
+        }
+    }
+
+    // called on every action by a key on the keychain, pushing back the beneficiary's claim
+    pub fn record_activity(&mut self) -> Result<()> {
+        self.last_active = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    pub fn is_claimable(&self, now: i64) -> bool {
+        self.beneficiary.is_some() && self.inactivity_period > 0 && now >= self.last_active.saturating_add(self.inactivity_period)
+    }
+
+    // checks a signed intent is for this keychain, unexpired and using the next nonce, then bumps the nonce so it can't
+    // be replayed
+    pub fn use_intent(&mut self, intent: &Intent, now: i64) -> Result<()> {
+        require!(intent.keychain == self.keychain, KeychainError::InvalidIntent);
+        require!(intent.nonce == self.nonce, KeychainError::InvalidNonce);
+        require!(now <= intent.expiry, KeychainError::IntentExpired);
+        self.nonce += 1;
+        Ok(())
+    }
+
     pub fn pending_key(self) -> Option<Pubkey> {
         if self.pending_action.is_some() {
             return Some(self.pending_action.unwrap().key.clone());
@@ -167,7 +707,7 @@ impl KeyChainState {
 }
 
 // simple bitset for up to 8 bits
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, PartialEq, Eq, Debug)]
 pub struct SmallBitSet {
     data: u8,
 }
@@ -188,6 +728,10 @@ impl SmallBitSet {
     pub fn unset_index(&mut self, index: u8) {
         self.data &= !(1 << index);
     }
+
+    pub fn is_set(&self, index: u8) -> bool {
+        self.data & (1 << index) != 0
+    }
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
@@ -195,14 +739,16 @@ pub struct PendingKeyChainAction {
     pub action_type: KeyChainActionType,
     pub key: Pubkey,
     pub verified: bool,
-    pub votes: SmallBitSet
+    pub votes: SmallBitSet,
+    // keys that voted against (only matters if the domain has a rejection threshold)
+    pub rejections: SmallBitSet,
 }
 
 impl PendingKeyChainAction {
-    pub const MAX_SIZE: usize = 1 + 32 + 1 + 1;
+    pub const MAX_SIZE: usize = KeyChainActionType::MAX_SIZE + 32 + 1 + 1 + 1;
 
     pub fn new(action_type: KeyChainActionType, key: Pubkey) -> Self {
-        Self { action_type, key, verified: false, votes: SmallBitSet::new() }
+        Self { action_type, key, verified: false, votes: SmallBitSet::new(), rejections: SmallBitSet::new() }
     }
 
     pub fn verify(&mut self) {
@@ -222,16 +768,209 @@ impl PendingKeyChainAction {
     }
 }
 
-// strictly for versioning info
+// an action signed off-chain by a key on the keychain, so a relayer can submit (and pay for) it. the ed25519 signature
+// over INTENT_PREFIX + the serialized intent gets checked by the ed25519 program in the preceding instruction
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Intent {
+    pub keychain: Pubkey,
+    pub action: IntentAction,
+    // has to match the keychain state's nonce
+    pub nonce: u64,
+    // unix timestamp after which the intent can't be used
+    pub expiry: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub enum IntentAction {
+    AddKey { key: Pubkey },
+    // the pending action being voted on, so the vote can't be used on a different one
+    Vote { vote: bool, action_type: KeyChainActionType, key: Pubkey },
+    RemoveKey { key: Pubkey },
+    // yardsale listing actions (checked through use_intent)
+    // actions for another program (which has to sign for use_intent with its INTENT_AUTHORITY pda)
+    Delist { program: Pubkey, listing: Pubkey },
+    UpdatePrice { program: Pubkey, listing: Pubkey, price: u64 },
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Debug)]
+pub struct ProposedAccountMeta {
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+// an instruction the keychain (or its vault) will sign for once the Execute action reaches the threshold
+#[account]
+pub struct KeyChainProposal {
+    // the keychain this proposal is for
+    pub keychain: Pubkey,
+    // the key that proposed (and paid for) it, which gets the rent back when it's closed
+    pub proposer: Pubkey,
+    pub program_id: Pubkey,
+    pub accounts: Vec<ProposedAccountMeta>,
+    pub data: Vec<u8>,
+    // sign with the vault pda instead of the keychain pda
+    pub use_vault: bool,
+}
+
+impl KeyChainProposal {
+    pub fn size(num_accounts: usize, data_len: usize) -> usize {
+        32 +                            // keychain
+        32 +                            // proposer
+        32 +                            // program_id
+        (4 + (num_accounts * 34)) +     // accounts
+        (4 + data_len) +                // data
+        1                               // use_vault
+    }
+
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: self.program_id,
+            accounts: self.accounts.iter().map(|meta| {
+                if meta.is_writable {
+                    AccountMeta::new(meta.pubkey, meta.is_signer)
+                } else {
+                    AccountMeta::new_readonly(meta.pubkey, meta.is_signer)
+                }
+            }).collect(),
+            data: self.data.clone(),
+        }
+    }
+}
+
+// what a key did to the keychain - one per instruction that changes it
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ActivityType {
+    CreateActivityLog,
+    // proposals (the target is the pending action's key)
+    AddKey,
+    RemoveKey,
+    Withdraw,
+    ProposeInstruction,
+    SetKeyWeight,
+    SetActionThreshold,
+    ProposeMerge,
+    ProposeDetach,
+    SetBeneficiary,
+    // votes on the pending action
+    Approve,
+    Reject,
+    RetractVote,
+    VerifyKey,
+    Merge,
+    Detach,
+    UpdateDisplayName,
+    Claim,
+    MintIdentity,
+    // by the domain authority
+    ForceRemoveKey,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct ActivityEntry {
+    pub activity_type: ActivityType,
+    // the key that did it
+    pub actor: Pubkey,
+    // the key (or account) it was done to
+    pub target: Pubkey,
+    pub timestamp: i64,
+}
+
+impl ActivityEntry {
+    pub const SIZE: usize = 1 + 32 + 32 + 8;
+}
+
+// the keychain's recent activity, so wallets can show it without an indexer. keeps the last ACTIVITY_LOG_SIZE entries
+#[account]
+pub struct ActivityLog {
+    pub keychain: Pubkey,
+    pub bump: u8,
+    // where the next entry goes (the oldest entry once the log is full)
+    pub head: u16,
+    // everything recorded, including overwritten entries
+    pub count: u64,
+    pub entries: Vec<ActivityEntry>,
+}
+
+impl ActivityLog {
+    pub const MAX_SIZE: usize =
+        32 +    // keychain
+        1 +     // bump
+        2 +     // head
+        8 +     // count
+        4 + (ACTIVITY_LOG_SIZE * ActivityEntry::SIZE);  // entries
+
+    pub fn record(&mut self, activity_type: ActivityType, actor: Pubkey, target: Pubkey) -> Result<()> {
+        let entry = ActivityEntry { activity_type, actor, target, timestamp: Clock::get()?.unix_timestamp };
+        if self.entries.len() < ACTIVITY_LOG_SIZE {
+            self.entries.push(entry);
+        } else {
+            self.entries[usize::from(self.head)] = entry;
+        }
+        self.head = ((usize::from(self.head) + 1) % ACTIVITY_LOG_SIZE) as u16;
+        self.count += 1;
+        Ok(())
+    }
+}
+
+// versioning info + the domain's stats (so they don't need a scan of all the domain's accounts)
 
 #[account]
 pub struct DomainState {
     pub version: u8,
     // the domain this state is for
-    pub domain: Pubkey
+    pub domain: Pubkey,
+    pub bump: u8,
+    // stats - domains migrated from before these were tracked only count from the migration
+    pub num_keychains: u64,
+    pub num_keys: u64,
+    // key costs paid to the domain treasury
+    pub total_fees_collected: u64,
+    // 0 for migrated domains
+    pub created_at: i64,
 }
 
 impl DomainState {
-    // 32 byte name
-    pub const MAX_SIZE: usize = 1 + 32;
+    pub const MAX_SIZE: usize =
+        1 +     // version
+        32 +    // domain
+        1 +     // bump
+        8 +     // num_keychains
+        8 +     // num_keys
+        8 +     // total_fees_collected
+        8;      // created_at
+
+    // older domain states need migrate_domain_state first
+    pub fn checked_bump(&self) -> Result<u8> {
+        require!(self.version == CURRENT_DOMAIN_VERSION, KeychainError::InvalidKeychainVersion);
+        Ok(self.bump)
+    }
+
+    // these saturate since migrated domains start counting from 0
+    pub fn add_keychain(&mut self) {
+        self.num_keychains = self.num_keychains.saturating_add(1);
+    }
+
+    pub fn remove_keychain(&mut self) {
+        self.num_keychains = self.num_keychains.saturating_sub(1);
+    }
+
+    pub fn add_key(&mut self) {
+        self.num_keys = self.num_keys.saturating_add(1);
+    }
+
+    pub fn remove_key(&mut self) {
+        self.num_keys = self.num_keys.saturating_sub(1);
+    }
+
+    pub fn collect_fee(&mut self, amount: u64) {
+        self.total_fees_collected = self.total_fees_collected.saturating_add(amount);
+    }
+}
+
+// v1 domain state (before the bump was stored) - later versions only added fields after the domain
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct DomainStateV1 {
+    pub version: u8,
+    pub domain: Pubkey,
 }
@@ -9,13 +9,54 @@ pub const KEY_SPACE: &str = "keys";
 pub const DOMAIN_STATE: &str = "domain_state";
 // the space for keychain state
 pub const KEYCHAIN_STATE_SPACE: &str  = "keychain_states";
+// the space for keychain vaults (hold sol + own token accounts on behalf of the keychain)
+pub const VAULT_SPACE: &str = "vaults";
+// the space for proposed instructions (executed by the keychain once approved)
+pub const PROPOSAL_SPACE: &str = "proposals";
+// the space for blocklisted wallets (per domain)
+pub const BLOCKLIST_SPACE: &str = "blocklist";
+// seed for the (singleton) registry of all domains
+pub const DOMAIN_REGISTRY: &str = "domain_registry";
 
+// seed for the (singleton) protocol config - controlled by the upgrade authority
+pub const PROTOCOL_CONFIG: &str = "protocol_config";
+// the space for protocol approvals of reserved domain names
+pub const DOMAIN_APPROVAL_SPACE: &str = "domain_approvals";
+// the space for keychain identity mints (soulbound nfts)
+pub const IDENTITY_SPACE: &str = "identities";
+// the space for keychain activity logs
+pub const ACTIVITY_SPACE: &str = "activity";
 
-pub const CURRENT_KEYCHAIN_VERSION: u8 = 2;
-pub const CURRENT_KEY_VERSION: u8 = 0;
-pub const CURRENT_DOMAIN_VERSION: u8 = 1;
+// the open domain anyone can create a keychain in - created when the program is initialized
+pub const GLOBAL_DOMAIN: &str = "global";
+
+// prepended to signed intents so the signature can't be reused for anything else
+pub const INTENT_PREFIX: &str = "keychain_intent";
+// seed for the pda another program signs use_intent with, so only the program an intent names can use it
+pub const INTENT_AUTHORITY: &str = "intent_authority";
+
+
+pub const CURRENT_KEYCHAIN_VERSION: u8 = 11;
+pub const CURRENT_KEY_VERSION: u8 = 1;
+pub const CURRENT_DOMAIN_VERSION: u8 = 3;
 
 pub const DEFAULT_DOMAIN_KEYCHAIN_ACTION_THRESHOLD: u8 = 2;
 
+// every key gets 1 vote unless the keychain votes to change it
+pub const DEFAULT_KEY_WEIGHT: u8 = 1;
+
+// names get used as pda seeds, which max out at 32 bytes
+pub const MAX_NAME_LENGTH: usize = 32;
+pub const MIN_NAME_LENGTH: usize = 3;
+
+// display names aren't seeds, so they can be longer (in bytes, so fewer characters for non-ascii)
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+
 // allow up to 5 wallets for now - 2 num_keys + 4 vector + (space(T) * amount)
 pub const MAX_KEYS: usize = 5;
+
+// the number of entries an activity log keeps before it starts overwriting the oldest
+pub const ACTIVITY_LOG_SIZE: usize = 32;
+
+// the most keys a domain can allow: votes are tracked in an 8 bit set (keychain accounts have room for 8 keys in their extra space)
+pub const MAX_DOMAIN_KEYS: usize = 8;
@@ -6,6 +6,65 @@ use crate::program::Keychain;
 use crate::constant::*;
 use crate::error::*;
 
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token::{Mint, Token, TokenAccount};
+
+// one-time setup by the upgrade authority: creates the domain registry + the global domain
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = authority,
+        seeds = [DOMAIN_REGISTRY.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + DomainRegistry::size(1),
+    )]
+    pub domain_registry: Box<Account<'info, DomainRegistry>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [PROTOCOL_CONFIG.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + ProtocolConfig::size(&[], &[]),
+    )]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [GLOBAL_DOMAIN.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + CurrentDomain::MAX_SIZE,
+    )]
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [DOMAIN_STATE.as_bytes().as_ref(), GLOBAL_DOMAIN.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + DomainState::MAX_SIZE,
+    )]
+    pub domain_state: Box<Account<'info, DomainState>>,
+
+    // the global domain's treasury
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    #[account()]
+    pub treasury: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program <'info, System>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, Keychain>,
+
+    #[account(constraint = program_data.upgrade_authority_address == Some(authority.key()))]
+    pub program_data: Account<'info, ProgramData>,
+}
+
 #[derive(Accounts)]
 #[instruction(name: String)]
 pub struct CreateDomain<'info> {
@@ -28,6 +87,36 @@ pub struct CreateDomain<'info> {
     )]
     pub domain_state: Box<Account<'info, DomainState>>,
 
+    #[account(
+        mut,
+        seeds = [DOMAIN_REGISTRY.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = domain_registry.bump,
+        realloc = 8 + DomainRegistry::size(domain_registry.domains.len() + 1),
+        realloc::payer = authority,
+        realloc::zero = false,
+    )]
+    pub domain_registry: Box<Account<'info, DomainRegistry>>,
+
+    #[account(
+        seeds = [PROTOCOL_CONFIG.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = protocol_config.bump,
+    )]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    // receives the domain creation fee
+    /// CHECK: checked against the protocol config
+    #[account(mut, address = protocol_config.treasury @ KeychainError::InvalidTreasury)]
+    pub protocol_treasury: AccountInfo<'info>,
+
+    // needed to claim a reserved name (or when not on the allowlist)
+    #[account(
+        mut,
+        seeds = [name.as_bytes().as_ref(), DOMAIN_APPROVAL_SPACE.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        constraint = domain_approval.authority == authority.key() @ KeychainError::InvalidDomainApproval,
+    )]
+    pub domain_approval: Option<Box<Account<'info, DomainApproval>>>,
+
     #[account(mut)]
     pub authority: Signer<'info>,
     pub system_program: Program <'info, System>,
@@ -38,6 +127,208 @@ pub struct CreateDomain<'info> {
     pub treasury: AccountInfo<'info>,
 }
 
+#[derive(Accounts)]
+#[instruction(domain_creation_fee: u64, treasury: Pubkey, allowlist: Vec<Pubkey>, reserved_names: Vec<String>)]
+pub struct UpdateProtocolConfig<'info> {
+    #[account(
+        mut,
+        seeds = [PROTOCOL_CONFIG.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = protocol_config.bump,
+        realloc = 8 + ProtocolConfig::size(&allowlist, &reserved_names),
+        realloc::payer = authority,
+        realloc::zero = false,
+    )]
+    pub protocol_config: Box<Account<'info, ProtocolConfig>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program <'info, System>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, Keychain>,
+
+    #[account(constraint = program_data.upgrade_authority_address == Some(authority.key()))]
+    pub program_data: Account<'info, ProgramData>,
+}
+
+// lets a given wallet create a domain with a reserved name (or create any domain when not on the allowlist)
+
+#[derive(Accounts)]
+#[instruction(name: String)]
+pub struct ApproveDomain<'info> {
+    #[account(
+        init,
+        payer = authority,
+        seeds = [name.as_bytes().as_ref(), DOMAIN_APPROVAL_SPACE.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + DomainApproval::MAX_SIZE,
+    )]
+    pub domain_approval: Box<Account<'info, DomainApproval>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program <'info, System>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, Keychain>,
+
+    #[account(constraint = program_data.upgrade_authority_address == Some(authority.key()))]
+    pub program_data: Account<'info, ProgramData>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateDomainConfig<'info> {
+    #[account(mut, has_one = authority @ KeychainError::NotDomainAdmin)]
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    pub authority: Signer<'info>,
+}
+
+// domain admin only. blocklisted wallets can't be linked in the domain
+
+#[derive(Accounts)]
+#[instruction(key: Pubkey)]
+pub struct BlockKey<'info> {
+    #[account(has_one = authority @ KeychainError::NotDomainAdmin)]
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [key.as_ref(), BLOCKLIST_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + BlockedKey::MAX_SIZE,
+    )]
+    pub blocked_key: Box<Account<'info, BlockedKey>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program <'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnblockKey<'info> {
+    #[account(has_one = authority @ KeychainError::NotDomainAdmin)]
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    #[account(mut, has_one = domain, close = authority)]
+    pub blocked_key: Box<Account<'info, BlockedKey>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+// domain admin only. evicts a blocklisted key from its keychain without a vote
+
+#[derive(Accounts)]
+#[instruction(key: Pubkey)]
+pub struct ForceRemoveKey<'info> {
+    #[account(has_one = authority @ KeychainError::NotDomainAdmin)]
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    // the domain's stats
+    #[account(
+        mut,
+        seeds = [DOMAIN_STATE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = domain_state.checked_bump()?,
+    )]
+    pub domain_state: Box<Account<'info, DomainState>>,
+
+    #[account(
+        seeds = [key.as_ref(), BLOCKLIST_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+    )]
+    pub blocked_key: Box<Account<'info, BlockedKey>>,
+
+    #[account(
+        mut,
+        constraint = keychain.domain == domain.name @ KeychainError::InvalidKeychain,
+        constraint = keychain.has_key(&key) @ KeychainError::KeyNotFound,
+    )]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [key.as_ref(), KEY_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = keychain_key.checked_bump()?,
+    )]
+    pub keychain_key: Box<Account<'info, CurrentKeyChainKey>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: the removed key's wallet - gets the rent back
+    #[account(mut, address = key @ KeychainError::InvalidDestination)]
+    pub wallet: AccountInfo<'info>,
+
+    // required (with the token program) if the removed key holds the keychain's identity nft, which gets burned
+    #[account(
+        mut,
+        seeds = [keychain.key().as_ref(), IDENTITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+    )]
+    pub identity_mint: Option<Box<Account<'info, Mint>>>,
+
+    #[account(mut)]
+    pub identity_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    pub token_program: Option<Program<'info, Token>>,
+
+    // required if the keychain gets closed, which it can't be until the vault's empty (the vault's token accounts go in
+    // remaining accounts)
+    #[account(
+        seeds = [keychain.key().as_ref(), VAULT_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+    )]
+    pub vault: Option<SystemAccount<'info>>,
+
+    // required once the keychain has an activity log - closed along with the keychain
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+// adds a domain created before the registry existed - upgrade authority only
+
+#[derive(Accounts)]
+pub struct RegisterDomain<'info> {
+    #[account()]
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    #[account(
+        mut,
+        seeds = [DOMAIN_REGISTRY.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = domain_registry.bump,
+        constraint = !domain_registry.domains.contains(&domain.key()) @ KeychainError::DomainAlreadyRegistered,
+        realloc = 8 + DomainRegistry::size(domain_registry.domains.len() + 1),
+        realloc::payer = authority,
+        realloc::zero = false,
+    )]
+    pub domain_registry: Box<Account<'info, DomainRegistry>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program <'info, System>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, Keychain>,
+
+    #[account(constraint = program_data.upgrade_authority_address == Some(authority.key()))]
+    pub program_data: Account<'info, ProgramData>,
+}
+
 // used to destroy a Domain, keychain, key or whatever keychain-owned account we want. note: use with extreme caution
 
 #[derive(Accounts)]
@@ -96,6 +387,23 @@ pub struct CreateKeychain<'info> {
     #[account()]
     pub domain: Box<Account<'info, CurrentDomain>>,
 
+    // the domain's stats
+    #[account(
+        mut,
+        seeds = [DOMAIN_STATE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = domain_state.checked_bump()?,
+    )]
+    pub domain_state: Box<Account<'info, DomainState>>,
+
+
+    // must not exist - the domain hasn't blocklisted the wallet
+    /// CHECK: only checked for existence
+    #[account(
+        seeds = [wallet.key().as_ref(), BLOCKLIST_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        constraint = blocked_key.data_is_empty() @ KeychainError::KeyBlocked,
+    )]
+    pub blocked_key: AccountInfo<'info>,
     /// CHECK: This is not dangerous because we don't read or write from this account
     pub wallet: AccountInfo<'info>,
 
@@ -105,6 +413,65 @@ pub struct CreateKeychain<'info> {
     pub system_program: Program <'info, System>,
 }
 
+// a keychain whose pda is derived from the creating wallet instead of a name
+#[derive(Accounts)]
+pub struct CreateWalletKeychain<'info> {
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [authority.key().as_ref(), KEYCHAIN_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + CurrentKeyChain::MAX_SIZE
+    )]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [keychain.key().as_ref(), KEYCHAIN_STATE_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + KeyChainState::MAX_SIZE
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [authority.key().as_ref(), KEY_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + KeyChainKey::MAX_SIZE
+    )]
+    // the first key on this keychain
+    pub keychain_key: Box<Account<'info, KeyChainKey>>,
+
+    #[account()]
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    // the domain's stats
+    #[account(
+        mut,
+        seeds = [DOMAIN_STATE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = domain_state.checked_bump()?,
+    )]
+    pub domain_state: Box<Account<'info, DomainState>>,
+
+
+    // must not exist - the domain hasn't blocklisted the wallet
+    /// CHECK: only checked for existence
+    #[account(
+        seeds = [authority.key().as_ref(), BLOCKLIST_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        constraint = blocked_key.data_is_empty() @ KeychainError::KeyBlocked,
+    )]
+    pub blocked_key: AccountInfo<'info>,
+    // the wallet the keychain is derived from (and its first key)
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program <'info, System>,
+}
+
 // just for testing - only super-admin can call this
 #[derive(Accounts)]
 #[instruction(keychain_name: String)]
@@ -162,38 +529,154 @@ pub struct CreateKeychainV1<'info> {
 #[instruction(key: Pubkey)]
 pub struct AddKey<'info> {
 
-    #[account(mut, constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    // the authority (or the intent's signer when relayed) gets checked in the handler
+    #[account(mut)]
     pub keychain: Box<Account<'info, CurrentKeyChain>>,
 
-    #[account(mut, has_one = keychain)]
+    #[account(mut, has_one = keychain, constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion)]
     pub keychain_state: Box<Account<'info, KeyChainState>>,
 
-    #[account(mut, constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
-    pub authority: Signer<'info>,
+    // for the domain's key limit
+    #[account(seeds = [keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()], bump = domain.bump)]
+    pub domain: Box<Account<'info, CurrentDomain>>,
 
-}
 
-#[derive(Accounts)]
+    // must not exist - the domain hasn't blocklisted the wallet
+    /// CHECK: only checked for existence
+    #[account(
+        seeds = [key.as_ref(), BLOCKLIST_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        constraint = blocked_key.data_is_empty() @ KeychainError::KeyBlocked,
+    )]
+    pub blocked_key: AccountInfo<'info>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    // required for relay_add_key
+    /// CHECK: address below
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
 pub struct VotePendingAction<'info> {
 
-    // check that the key being verified has already been added to the keychain & check auth on the authority below
-    #[account(mut, constraint = keychain.has_key(&authority.key()) @ KeychainError::KeyNotFound)]
+    // the authority (or the intent's signer when relayed) gets checked in the handler
+    #[account(mut)]
     pub keychain: Box<Account<'info, CurrentKeyChain>>,
 
-    #[account(mut, has_one = keychain, constraint = keychain_state.has_pending_action() @ KeychainError::NoPendingAction)]
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = keychain_state.has_pending_action() @ KeychainError::NoPendingAction,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
     pub keychain_state: Box<Account<'info, KeyChainState>>,
 
     // this is required if the pending action is a key removal
     #[account(
         mut,
         seeds = [keychain_state.pending_action.as_ref().unwrap().key.as_ref(), KEY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
-        bump,
+        bump = keychain_key.checked_bump()?,
     )]
-    pub keychain_key: Option<Account<'info, KeyChainKey>>,
+    pub keychain_key: Option<Account<'info, CurrentKeyChainKey>>,
 
-    #[account(mut, constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    #[account(mut)]
     pub authority: Signer<'info>,
 
+    // the rest are required if the pending action is a withdrawal
+
+    #[account(
+        mut,
+        seeds = [keychain.key().as_ref(), VAULT_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+    )]
+    pub vault: Option<SystemAccount<'info>>,
+
+    // the vault's token account (spl withdrawals)
+    #[account(mut)]
+    pub vault_token: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: sol withdrawals (and refunds for rejected keys) get sent here, checked against the pending action's key
+    #[account(mut)]
+    pub destination: Option<AccountInfo<'info>>,
+
+    // spl withdrawals get sent here, checked against the pending action's key
+    #[account(mut)]
+    pub destination_token: Option<Account<'info, TokenAccount>>,
+
+    pub token_program: Option<Program<'info, Token>>,
+    pub system_program: Option<Program<'info, System>>,
+
+    // required if the pending action is an execute (accounts for the instruction are passed in remaining accounts)
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), PROPOSAL_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+    )]
+    pub proposal: Option<Account<'info, KeyChainProposal>>,
+
+    /// CHECK: required with the proposal, which gets closed to here once it's executed or rejected. checked against the
+    /// proposal's proposer
+    #[account(mut)]
+    pub proposer: Option<AccountInfo<'info>>,
+
+    // required when adding a verified key (the key cost held in the key account gets paid to the domain treasury) and
+    // when rejecting (for the domain's rejection threshold)
+    #[account(seeds = [keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()], bump = domain.bump)]
+    pub domain: Option<Box<Account<'info, CurrentDomain>>>,
+
+    /// CHECK: just receiving lamports, checked against the domain's treasury
+    #[account(mut)]
+    pub treasury: Option<AccountInfo<'info>>,
+
+    // required for relay_vote
+    /// CHECK: address below
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    // required for relay_vote if the vote closes a key account - the intent signer's wallet, which gets the rent
+    // (checked in the handler)
+    #[account(mut)]
+    pub wallet: Option<SystemAccount<'info>>,
+
+    // required (with the token program) if the key being removed holds the keychain's identity nft, which gets burned
+    #[account(
+        mut,
+        seeds = [keychain.key().as_ref(), IDENTITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+    )]
+    pub identity_mint: Option<Box<Account<'info, Mint>>>,
+
+    #[account(mut)]
+    pub identity_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    // required when a key gets added or removed (for the domain's stats)
+    #[account(
+        mut,
+        seeds = [DOMAIN_STATE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = domain_state.checked_bump()?,
+    )]
+    pub domain_state: Option<Box<Account<'info, DomainState>>>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
 }
 
 #[derive(Accounts)]
@@ -202,10 +685,32 @@ pub struct VerifyKey<'info> {
     #[account(has_one = treasury @KeychainError::InvalidTreasury)]
     pub domain: Box<Account<'info, CurrentDomain>>,
 
+    // the domain's stats
+    #[account(
+        mut,
+        seeds = [DOMAIN_STATE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = domain_state.checked_bump()?,
+    )]
+    pub domain_state: Box<Account<'info, DomainState>>,
+
+
+    // must not exist - the domain hasn't blocklisted the wallet
+    /// CHECK: only checked for existence
+    #[account(
+        seeds = [authority.key().as_ref(), BLOCKLIST_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        constraint = blocked_key.data_is_empty() @ KeychainError::KeyBlocked,
+    )]
+    pub blocked_key: AccountInfo<'info>,
     #[account(mut)]
     pub keychain: Box<Account<'info, CurrentKeyChain>>,
 
-    #[account(mut, has_one = keychain, constraint = keychain_state.has_pending_action_type(KeyChainActionType::AddKey) @ KeychainError::NoPendingAction)]
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = keychain_state.has_pending_action_type(KeyChainActionType::AddKey) @ KeychainError::NoPendingAction,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
     pub keychain_state: Box<Account<'info, KeyChainState>>,
 
     // the key account gets created here
@@ -214,7 +719,7 @@ pub struct VerifyKey<'info> {
         payer = authority,
         seeds = [&authority.key().as_ref(), KEY_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
         bump,
-        space = 8 + (32 * 2)
+        space = 8 + KeyChainKey::SIZE
     )]
     pub keychain_key: Box<Account<'info, KeyChainKey>>,
 
@@ -227,6 +732,86 @@ pub struct VerifyKey<'info> {
     pub treasury: AccountInfo<'info>,
 
     pub system_program: Program <'info, System>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
+pub struct VerifyProgramKey<'info> {
+
+    #[account(has_one = treasury @KeychainError::InvalidTreasury)]
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    // the domain's stats
+    #[account(
+        mut,
+        seeds = [DOMAIN_STATE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = domain_state.checked_bump()?,
+    )]
+    pub domain_state: Box<Account<'info, DomainState>>,
+
+    // must not exist - the domain hasn't blocklisted the key
+    /// CHECK: only checked for existence
+    #[account(
+        seeds = [authority.key().as_ref(), BLOCKLIST_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        constraint = blocked_key.data_is_empty() @ KeychainError::KeyBlocked,
+    )]
+    pub blocked_key: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = keychain_state.has_pending_action_type(KeyChainActionType::AddKey) @ KeychainError::NoPendingAction,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    // the key account gets created here
+    #[account(
+        init,
+        payer = payer,
+        seeds = [&authority.key().as_ref(), KEY_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + KeyChainKey::SIZE
+    )]
+    pub keychain_key: Box<Account<'info, KeyChainKey>>,
+
+    // the pending pda key - signed for by its owning program with invoke_signed
+    #[account(
+        constraint = keychain_state.has_pending_action_key(&authority.key()) @ KeychainError::InvalidVerifier,
+        constraint = !authority.key().is_on_curve() @ KeychainError::NotProgramKey,
+    )]
+    pub authority: Signer<'info>,
+
+    // pays for the key account + key cost (a pda holding data can't)
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: just sending lamports
+    #[account(mut, address = domain.treasury, constraint = treasury.key() == domain.treasury @ KeychainError::InvalidTreasury)]
+    pub treasury: AccountInfo<'info>,
+
+    pub system_program: Program <'info, System>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
 }
 
 #[derive(Accounts)]
@@ -238,7 +823,12 @@ pub struct RemoveKey<'info> {
     pub keychain: Box<Account<'info, CurrentKeyChain>>,
 
     // include the state in case the keychain is closed; make sure there's no pending action
-    #[account(mut, has_one = keychain, constraint = !keychain_state.has_pending_action() @ KeychainError::PendingActionExists)]
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = !keychain_state.has_pending_action() @ KeychainError::PendingActionExists,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
     pub keychain_state: Box<Account<'info, KeyChainState>>,
 
     // the key account that will need to be removed
@@ -246,13 +836,62 @@ pub struct RemoveKey<'info> {
     #[account(
         mut,
         seeds = [key.as_ref(), KEY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
-        bump,
+        bump = keychain_key.checked_bump()?,
     )]
-    pub keychain_key: Box<Account<'info, KeyChainKey>>,
+    pub keychain_key: Box<Account<'info, CurrentKeyChainKey>>,
 
-    #[account(mut, constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    // the authority (or the intent's signer when relayed) gets checked in the handler
+    #[account(mut)]
     pub authority: Signer<'info>,
 
+    // required for relay_remove_key
+    /// CHECK: address below
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    // required for relay_remove_key if the keychain gets closed - the intent signer's wallet, which gets the rent
+    // (checked in the handler)
+    #[account(mut)]
+    pub wallet: Option<SystemAccount<'info>>,
+
+    // required if the keychain gets closed and its only key holds the identity nft, which gets burned
+    #[account(
+        mut,
+        seeds = [keychain.key().as_ref(), IDENTITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+    )]
+    pub identity_mint: Option<Box<Account<'info, Mint>>>,
+
+    #[account(mut)]
+    pub identity_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    pub token_program: Option<Program<'info, Token>>,
+
+    // required if the keychain gets closed, which it can't be until the vault's empty (the vault's token accounts go in
+    // remaining accounts)
+    #[account(
+        seeds = [keychain.key().as_ref(), VAULT_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+    )]
+    pub vault: Option<SystemAccount<'info>>,
+
+    // required if the keychain gets closed (for the domain's stats)
+    #[account(
+        mut,
+        seeds = [DOMAIN_STATE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = domain_state.checked_bump()?,
+    )]
+    pub domain_state: Option<Box<Account<'info, DomainState>>>,
+
+    // required once the keychain has an activity log - closed along with the keychain
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+
     /*
     // #[account(has_one = treasury OR constraint = domain.treasury == treasury.key() @ KeychainError::InvalidTreasury)]
     #[account()]
@@ -263,3 +902,752 @@ pub struct RemoveKey<'info> {
     pub treasury: AccountInfo<'info>
      */
 }
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+
+    #[account(constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    // the vault doesn't hold any data, it just holds sol and owns token accounts
+    #[account(
+        mut,
+        seeds = [keychain.key().as_ref(), VAULT_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+    )]
+    pub vault: SystemAccount<'info>,
+
+    // the depositor's token account - only needed for spl deposits
+    #[account(mut, token::authority = authority)]
+    pub authority_token: Option<Account<'info, TokenAccount>>,
+
+    // the vault's token account - only needed for spl deposits
+    #[account(mut, token::authority = vault)]
+    pub vault_token: Option<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub token_program: Option<Program<'info, Token>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+
+    #[account(constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = !keychain_state.has_pending_action() @ KeychainError::PendingActionExists,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    pub authority: Signer<'info>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
+#[instruction(target_program: Pubkey, metas: Vec<ProposedAccountMeta>, data: Vec<u8>)]
+pub struct ProposeInstruction<'info> {
+
+    #[account(constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = !keychain_state.has_pending_action() @ KeychainError::PendingActionExists,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    // only one proposal at a time, same as pending actions
+    #[account(
+        init,
+        payer = authority,
+        seeds = [keychain.key().as_ref(), PROPOSAL_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + KeyChainProposal::size(metas.len(), data.len())
+    )]
+    pub proposal: Box<Account<'info, KeyChainProposal>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
+#[instruction(key: Pubkey)]
+pub struct SetKeyWeight<'info> {
+
+    #[account(
+        constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized,
+        constraint = keychain.has_key(&key) @ KeychainError::KeyNotFound,
+    )]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = !keychain_state.has_pending_action() @ KeychainError::PendingActionExists,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    pub authority: Signer<'info>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
+pub struct SetActionThreshold<'info> {
+
+    #[account(constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = !keychain_state.has_pending_action() @ KeychainError::PendingActionExists,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    pub authority: Signer<'info>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
+pub struct SetBeneficiary<'info> {
+
+    #[account(constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = !keychain_state.has_pending_action() @ KeychainError::PendingActionExists,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    pub authority: Signer<'info>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
+pub struct Heartbeat<'info> {
+    #[account(constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(mut, has_one = keychain, constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion)]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimKeychain<'info> {
+
+    #[account(seeds = [keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()], bump = domain.bump, has_one = treasury @ KeychainError::InvalidTreasury)]
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    // the domain's stats
+    #[account(
+        mut,
+        seeds = [DOMAIN_STATE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = domain_state.checked_bump()?,
+    )]
+    pub domain_state: Box<Account<'info, DomainState>>,
+
+    // must not exist - the domain hasn't blocklisted the beneficiary
+    /// CHECK: only checked for existence
+    #[account(
+        seeds = [beneficiary.key().as_ref(), BLOCKLIST_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        constraint = blocked_key.data_is_empty() @ KeychainError::KeyBlocked,
+    )]
+    pub blocked_key: AccountInfo<'info>,
+
+    #[account(mut, constraint = !keychain.has_key(&beneficiary.key()) @ KeychainError::KeyAlreadyExists)]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = keychain_state.beneficiary == Some(beneficiary.key()) @ KeychainError::NotBeneficiary,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    // the beneficiary's key account gets created here
+    #[account(
+        init,
+        payer = authority,
+        seeds = [beneficiary.key().as_ref(), KEY_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + KeyChainKey::SIZE
+    )]
+    pub keychain_key: Box<Account<'info, KeyChainKey>>,
+
+    /// CHECK: doesn't need to sign - checked against the keychain state's beneficiary
+    pub beneficiary: AccountInfo<'info>,
+
+    // anyone can trigger the claim (and pays for the key)
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: just sending lamports
+    #[account(mut)]
+    pub treasury: AccountInfo<'info>,
+
+    pub system_program: Program <'info, System>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
+pub struct GetHoldings<'info> {
+    // the token accounts (+ metadata accounts for collections) get passed in remaining accounts
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+}
+
+#[derive(Accounts)]
+pub struct UseIntent<'info> {
+
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    //sysvar ixs don't deserialize in anchor
+    /// CHECK: address below
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    // the calling program's INTENT_AUTHORITY pda, checked against the program the intent names in the handler
+    pub program_signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateKey<'info> {
+
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    /// CHECK: just used for the key account's seeds
+    pub wallet: UncheckedAccount<'info>,
+
+    /// CHECK: older key accounts are too small for the current layout, so this gets checked + written in the handler
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [wallet.key().as_ref(), KEY_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+    )]
+    pub keychain_key: UncheckedAccount<'info>,
+
+    // covers the rent for any added space
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateDomainState<'info> {
+
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    /// CHECK: too small for the current layout, so this gets checked + written in the handler
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [DOMAIN_STATE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+    )]
+    pub domain_state: UncheckedAccount<'info>,
+
+    // covers the rent for the added space
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpgradeKeychain<'info> {
+
+    /// CHECK: still in the old layout, so it gets deserialized manually in the handler
+    #[account(mut, owner = crate::ID)]
+    pub keychain: AccountInfo<'info>,
+
+    // v2+ keychains can be upgraded in place - the version + keychain get checked in the handler
+    /// CHECK: also in the old layout
+    #[account(mut, owner = crate::ID)]
+    pub keychain_state: AccountInfo<'info>,
+
+    // covers the rent for any added space
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateDisplayName<'info> {
+    #[account(constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(mut, has_one = keychain, constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion)]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    pub authority: Signer<'info>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeMerge<'info> {
+
+    #[account(constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = !keychain_state.has_pending_action() @ KeychainError::PendingActionExists,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    // the keychain to merge with - has to be in the same domain
+    #[account(
+        constraint = other_keychain.key() != keychain.key() @ KeychainError::InvalidMerge,
+        constraint = other_keychain.domain == keychain.domain @ KeychainError::InvalidMerge,
+    )]
+    pub other_keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    // for the key limit
+    #[account(seeds = [keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()], bump = domain.bump)]
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    pub authority: Signer<'info>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
+pub struct MergeKeychains<'info> {
+
+    // gets merged into the target, then closed
+    #[account(mut, close = authority, constraint = source.domain == target.domain @ KeychainError::InvalidMerge)]
+    pub source: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        close = authority,
+        constraint = source_state.keychain == source.key() @ KeychainError::InvalidMerge,
+        constraint = source_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+        constraint = source_state.has_approved_merge(&target.key(), true) @ KeychainError::InvalidMerge,
+    )]
+    pub source_state: Box<Account<'info, KeyChainState>>,
+
+    #[account(mut)]
+    pub target: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        constraint = target_state.keychain == target.key() @ KeychainError::InvalidMerge,
+        constraint = target_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+        constraint = target_state.has_approved_merge(&source.key(), false) @ KeychainError::InvalidMerge,
+    )]
+    pub target_state: Box<Account<'info, KeyChainState>>,
+
+    #[account(seeds = [target.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()], bump = domain.bump)]
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    // the domain's stats
+    #[account(
+        mut,
+        seeds = [DOMAIN_STATE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = domain_state.checked_bump()?,
+    )]
+    pub domain_state: Box<Account<'info, DomainState>>,
+
+    // any key on either keychain - gets the source's rent
+    #[account(mut, constraint = source.has_key(&authority.key()) || target.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    pub authority: Signer<'info>,
+
+    // has to be empty, since the source gets closed (its token accounts go in remaining accounts, after the key accounts)
+    #[account(
+        seeds = [source.key().as_ref(), VAULT_SPACE.as_bytes().as_ref(), source.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+    )]
+    pub source_vault: SystemAccount<'info>,
+
+    // required (with the token program) if a source key holds the source's identity nft, which gets burned
+    #[account(
+        mut,
+        seeds = [source.key().as_ref(), IDENTITY_SPACE.as_bytes().as_ref(), source.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+    )]
+    pub source_identity_mint: Option<Box<Account<'info, Mint>>>,
+
+    #[account(mut)]
+    pub source_identity_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    pub token_program: Option<Program<'info, Token>>,
+
+    // required once the source has an activity log - closed along with the source
+    #[account(
+        mut,
+        close = authority,
+        constraint = source_activity_log.keychain == source.key() @ KeychainError::MissingActivityLog,
+        seeds = [source.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), source.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = source_activity_log.bump,
+    )]
+    pub source_activity_log: Option<Box<Account<'info, ActivityLog>>>,
+
+    // required once the target has an activity log
+    #[account(
+        mut,
+        constraint = target_activity_log.keychain == target.key() @ KeychainError::MissingActivityLog,
+        seeds = [target.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), target.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = target_activity_log.bump,
+    )]
+    pub target_activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
+#[instruction(key: Pubkey)]
+pub struct ProposeDetach<'info> {
+
+    // the only key can't be detached
+    #[account(
+        constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized,
+        constraint = keychain.has_key(&key) @ KeychainError::KeyNotFound,
+        constraint = keychain.num_keys > 1 @ KeychainError::KeyNotFound,
+    )]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = !keychain_state.has_pending_action() @ KeychainError::PendingActionExists,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    // for the name rules
+    #[account(seeds = [keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()], bump = domain.bump)]
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    pub authority: Signer<'info>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
+#[instruction(name: String)]
+pub struct DetachKey<'info> {
+
+    // checked again here since a key can be force removed between the approval and the detach, which would otherwise
+    // leave the keychain with no keys
+    #[account(
+        mut,
+        constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized,
+        constraint = keychain.num_keys > 1 @ KeychainError::KeyNotFound,
+    )]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = keychain_state.has_approved_detach(&name) @ KeychainError::NoPendingAction,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    // the detached key's account - gets re-pointed to the new keychain
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain_state.pending_action.as_ref().unwrap().key.as_ref(), KEY_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = keychain_key.checked_bump()?,
+    )]
+    pub keychain_key: Box<Account<'info, CurrentKeyChainKey>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [name.as_bytes().as_ref(), KEYCHAIN_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + CurrentKeyChain::MAX_SIZE,
+    )]
+    pub new_keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [new_keychain.key().as_ref(), KEYCHAIN_STATE_SPACE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + KeyChainState::MAX_SIZE,
+    )]
+    pub new_keychain_state: Box<Account<'info, KeyChainState>>,
+
+    #[account(seeds = [keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()], bump = domain.bump)]
+    pub domain: Box<Account<'info, CurrentDomain>>,
+
+    // the domain's stats
+    #[account(
+        mut,
+        seeds = [DOMAIN_STATE.as_bytes().as_ref(), domain.name.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = domain_state.checked_bump()?,
+    )]
+    pub domain_state: Box<Account<'info, DomainState>>,
+
+    // any key on the keychain - pays for the new keychain
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program <'info, System>,
+
+    // required (with the token program) if the detached key holds the keychain's identity nft, which gets burned
+    #[account(
+        mut,
+        seeds = [keychain.key().as_ref(), IDENTITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+    )]
+    pub identity_mint: Option<Box<Account<'info, Mint>>>,
+
+    #[account(mut)]
+    pub identity_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    pub token_program: Option<Program<'info, Token>>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
+pub struct RetractVote<'info> {
+
+    #[account(constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = keychain_state.has_pending_action() @ KeychainError::NoPendingAction,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    pub authority: Signer<'info>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseOrphanedKey<'info> {
+
+    #[account(mut, has_one = keychain)]
+    pub keychain_key: Box<Account<'info, KeyChainKey>>,
+
+    /// CHECK: may have been closed, so gets checked in the handler
+    pub keychain: AccountInfo<'info>,
+
+    // required if the keychain still exists (to check the key isn't pending)
+    #[account(has_one = keychain)]
+    pub keychain_state: Option<Box<Account<'info, KeyChainState>>>,
+
+    /// CHECK: receives the key account's lamports
+    #[account(mut, address = keychain_key.key @ KeychainError::InvalidDestination)]
+    pub wallet: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MintIdentity<'info> {
+
+    #[account(constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    // one identity mint per keychain, created on the first mint - the keychain is the mint + freeze authority
+    #[account(
+        init_if_needed,
+        payer = authority,
+        seeds = [keychain.key().as_ref(), IDENTITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        mint::decimals = 0,
+        mint::authority = keychain,
+        mint::freeze_authority = keychain,
+    )]
+    pub identity_mint: Box<Account<'info, Mint>>,
+
+    // the new holder's token account
+    #[account(
+        init_if_needed,
+        payer = authority,
+        associated_token::mint = identity_mint,
+        associated_token::authority = authority,
+    )]
+    pub identity_token: Box<Account<'info, TokenAccount>>,
+
+    // the current holder's token account - required if the identity's already been minted (it gets burned)
+    #[account(mut, token::mint = identity_mint)]
+    pub previous_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// CHECK: created by the metadata program on the first mint
+    #[account(
+        mut,
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), identity_mint.key().as_ref()],
+        seeds::program = token_metadata_program.key(),
+        bump,
+    )]
+    pub metadata: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: address below
+    #[account(address = mpl_token_metadata::ID)]
+    pub token_metadata_program: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+
+    // required once the keychain has an activity log
+    #[account(
+        mut,
+        has_one = keychain,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump = activity_log.bump,
+    )]
+    pub activity_log: Option<Box<Account<'info, ActivityLog>>>,
+}
+
+#[derive(Accounts)]
+pub struct CreateActivityLog<'info> {
+
+    #[account(constraint = keychain.has_key(&authority.key()) @ KeychainError::NotAuthorized)]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    #[account(
+        mut,
+        has_one = keychain,
+        constraint = keychain_state.keychain_version == CURRENT_KEYCHAIN_VERSION @ KeychainError::InvalidKeychainVersion,
+    )]
+    pub keychain_state: Box<Account<'info, KeyChainState>>,
+
+    // one log per keychain
+    #[account(
+        init,
+        payer = authority,
+        seeds = [keychain.key().as_ref(), ACTIVITY_SPACE.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), KEYCHAIN.as_bytes().as_ref()],
+        bump,
+        space = 8 + ActivityLog::MAX_SIZE,
+    )]
+    pub activity_log: Box<Account<'info, ActivityLog>>,
+
+    // any key on the keychain - pays for the log
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
@@ -41,7 +41,75 @@ pub enum KeychainError {
     #[msg("A pending action doesn't exist")]
     NoPendingAction,
     #[msg("Key not verified")]
-    KeyNotVerified
+    KeyNotVerified,
+    #[msg("Invalid amount")]
+    InvalidAmount,
+    #[msg("Missing required vault account")]
+    MissingVaultAccount,
+    #[msg("Invalid vault account")]
+    InvalidVaultAccount,
+    #[msg("Invalid withdrawal destination")]
+    InvalidDestination,
+    #[msg("Missing required proposal account")]
+    MissingProposalAccount,
+    #[msg("Invalid proposal")]
+    InvalidProposal,
+    #[msg("Invalid key weight")]
+    InvalidWeight,
+    #[msg("Domain is already registered")]
+    DomainAlreadyRegistered,
+    #[msg("That domain name is reserved")]
+    ReservedName,
+    #[msg("Not allowed to create domains")]
+    DomainCreatorNotAllowed,
+    #[msg("Invalid domain approval")]
+    InvalidDomainApproval,
+    #[msg("Invalid domain config")]
+    InvalidDomainConfig,
+    #[msg("Invalid display name. Can't be blank or contain control characters")]
+    InvalidDisplayName,
+    #[msg("Display name too long. Max 64 bytes")]
+    DisplayNameTooLong,
+    #[msg("Key account still points to a keychain the key is on")]
+    KeyNotOrphaned,
+    #[msg("Key hasn't voted on the pending action")]
+    VoteNotFound,
+    #[msg("Missing required domain account")]
+    MissingDomainAccount,
+    #[msg("Invalid keychain merge")]
+    InvalidMerge,
+    #[msg("That wallet is blocked in this domain")]
+    KeyBlocked,
+    #[msg("Keychain isn't in this domain")]
+    InvalidKeychain,
+    #[msg("Inactivity period must be positive")]
+    InvalidInactivityPeriod,
+    #[msg("Not the keychain's beneficiary")]
+    NotBeneficiary,
+    #[msg("Keychain hasn't been inactive long enough to be claimed")]
+    KeychainStillActive,
+    #[msg("Missing or invalid ed25519 signature for the intent")]
+    InvalidIntentSignature,
+    #[msg("Intent doesn't match this instruction")]
+    InvalidIntent,
+    #[msg("Intent nonce has already been used")]
+    InvalidNonce,
+    #[msg("Intent has expired")]
+    IntentExpired,
+    #[msg("Key isn't a program-derived address")]
+    NotProgramKey,
+    #[msg("Token account isn't held by a key on the keychain, or doesn't match the mint/collection")]
+    InvalidHoldingsAccount,
+    #[msg("Missing the identity mint, token account or token program")]
+    MissingIdentityAccount,
+    #[msg("Identity token account doesn't match the mint or holder")]
+    InvalidIdentityAccount,
+    #[msg("Key already holds the keychain's identity")]
+    IdentityAlreadyHeld,
+    #[msg("Missing or invalid activity log account")]
+    MissingActivityLog,
+    #[msg("Keychain's vault still holds sol or tokens")]
+    VaultNotEmpty,
 
 
 }
@@ -16,6 +16,9 @@ pub mod context;
 pub mod account;
 mod util;
 
+// for programs that check keychain holdings directly instead of through get_holdings
+pub use util::keychain_holdings;
+
 // todo: use realloc to enable any number of keys instead of limiting to 5
 
 use constant::*;
@@ -26,14 +29,38 @@ use util::*;
 
 #[program]
 pub mod keychain {
-    use anchor_lang::{AccountsClose};
+    use anchor_lang::{AccountsClose, Discriminator};
+    use anchor_spl::associated_token::get_associated_token_address;
+    use anchor_spl::token::{self, Approve, FreezeAccount, MintTo, ThawAccount, Transfer};
+    use mpl_token_metadata::instruction::create_metadata_accounts_v3;
     use super::*;
 
     use anchor_lang::solana_program::{
-        program::{invoke},
+        program::{invoke, invoke_signed},
         system_instruction,
     };
 
+    // sets up the domain registry and the open global domain. the upgrade authority is the global domain's authority
+    pub fn initialize(ctx: Context<Initialize>, key_cost: u64) -> Result <()> {
+
+        init_domain(&mut ctx.accounts.domain, &mut ctx.accounts.domain_state, GLOBAL_DOMAIN.to_string(),
+                    ctx.accounts.authority.key(), ctx.accounts.treasury.key(), key_cost, *ctx.bumps.get("domain").unwrap(),
+                    *ctx.bumps.get("domain_state").unwrap())?;
+
+        let domain_registry = &mut ctx.accounts.domain_registry;
+        domain_registry.bump = *ctx.bumps.get("domain_registry").unwrap();
+        domain_registry.domains = vec![ctx.accounts.domain.key()];
+
+        // open domain creation (no fee or allowlist) until the config gets updated. reserved names can be claimed with an approval
+        let protocol_config = &mut ctx.accounts.protocol_config;
+        protocol_config.bump = *ctx.bumps.get("protocol_config").unwrap();
+        protocol_config.treasury = ctx.accounts.treasury.key();
+        protocol_config.allow_reserved_with_approval = true;
+
+        msg!("created global domain account: {}", ctx.accounts.domain.key());
+        Ok(())
+    }
+
     pub fn create_domain(ctx: Context<CreateDomain>, name: String, key_cost: u64) -> Result <()> {
 
         // check name length <= 32
@@ -42,22 +69,144 @@ pub mod keychain {
         let is_valid_name = is_valid_name(&name);
         require!(is_valid_name, KeychainError::InvalidName);
 
-        let domain_state = &mut ctx.accounts.domain_state;
-        domain_state.version = CURRENT_DOMAIN_VERSION;
-        domain_state.domain = ctx.accounts.domain.key();
+        // an approval (for this name + authority) gets around the allowlist and reserved names
+        let protocol_config = &ctx.accounts.protocol_config;
+        if let Some(domain_approval) = &ctx.accounts.domain_approval {
+            require!(protocol_config.allow_reserved_with_approval || !protocol_config.is_reserved(&name), KeychainError::ReservedName);
+            domain_approval.close(ctx.accounts.authority.to_account_info())?;
+        } else {
+            require!(!protocol_config.is_reserved(&name), KeychainError::ReservedName);
+            require!(protocol_config.is_allowed(ctx.accounts.authority.key), KeychainError::DomainCreatorNotAllowed);
+        }
 
-        let domain = &mut ctx.accounts.domain;
-        domain.name = name;
-        domain.authority = *ctx.accounts.authority.key;
-        domain.key_cost = key_cost;
-        domain.treasury = *ctx.accounts.treasury.key;
-        domain.bump = *ctx.bumps.get("domain").unwrap();
-        domain.keychain_action_threshold = DEFAULT_DOMAIN_KEYCHAIN_ACTION_THRESHOLD;
+        if protocol_config.domain_creation_fee > 0 {
+            invoke(
+                &system_instruction::transfer(
+                    ctx.accounts.authority.key,
+                    ctx.accounts.protocol_treasury.key,
+                    protocol_config.domain_creation_fee,
+                ),
+                &[
+                    ctx.accounts.authority.to_account_info().clone(),
+                    ctx.accounts.protocol_treasury.clone(),
+                    ctx.accounts.system_program.to_account_info().clone(),
+                ],
+            )?;
+        }
+
+        init_domain(&mut ctx.accounts.domain, &mut ctx.accounts.domain_state, name,
+                    ctx.accounts.authority.key(), ctx.accounts.treasury.key(), key_cost, *ctx.bumps.get("domain").unwrap(),
+                    *ctx.bumps.get("domain_state").unwrap())?;
+
+        ctx.accounts.domain_registry.domains.push(ctx.accounts.domain.key());
 
         msg!("created domain account: {}", ctx.accounts.domain.key());
         Ok(())
     }
 
+    // upgrade authority only. an empty allowlist means anyone can create a domain
+    pub fn update_protocol_config(ctx: Context<UpdateProtocolConfig>, domain_creation_fee: u64, treasury: Pubkey,
+                                  allowlist: Vec<Pubkey>, reserved_names: Vec<String>, allow_reserved_with_approval: bool) -> Result <()> {
+        let protocol_config = &mut ctx.accounts.protocol_config;
+        protocol_config.domain_creation_fee = domain_creation_fee;
+        protocol_config.treasury = treasury;
+        protocol_config.allowlist = allowlist;
+        protocol_config.reserved_names = reserved_names;
+        protocol_config.allow_reserved_with_approval = allow_reserved_with_approval;
+        Ok(())
+    }
+
+    // upgrade authority only. lets the given wallet claim a domain name
+    pub fn approve_domain(ctx: Context<ApproveDomain>, name: String, authority: Pubkey) -> Result <()> {
+        let domain_approval = &mut ctx.accounts.domain_approval;
+        domain_approval.name = name;
+        domain_approval.authority = authority;
+        Ok(())
+    }
+
+    // domain admin only. sets the rules for keychains in the domain
+    pub fn update_domain_config(ctx: Context<UpdateDomainConfig>, max_keys: u8, min_name_len: u8, max_name_len: u8, name_charset: NameCharset,
+                                rejection_threshold: u8) -> Result <()> {
+        require!(max_keys > 0 && usize::from(max_keys) <= MAX_DOMAIN_KEYS, KeychainError::InvalidDomainConfig);
+        require!(min_name_len > 0 && min_name_len <= max_name_len && usize::from(max_name_len) <= MAX_NAME_LENGTH, KeychainError::InvalidDomainConfig);
+
+        let domain = &mut ctx.accounts.domain;
+        domain.max_keys = max_keys;
+        domain.min_name_len = min_name_len;
+        domain.max_name_len = max_name_len;
+        domain.name_charset = name_charset;
+        domain.rejection_threshold = rejection_threshold;
+        Ok(())
+    }
+
+    pub fn block_key(ctx: Context<BlockKey>, key: Pubkey) -> Result <()> {
+        let blocked_key = &mut ctx.accounts.blocked_key;
+        blocked_key.domain = ctx.accounts.domain.key();
+        blocked_key.key = key;
+        msg!("blocked key {} in domain {}", key, ctx.accounts.domain.name);
+        Ok(())
+    }
+
+    pub fn unblock_key(ctx: Context<UnblockKey>) -> Result <()> {
+        msg!("unblocked key {} in domain {}", ctx.accounts.blocked_key.key, ctx.accounts.domain.name);
+        Ok(())
+    }
+
+    // removes a blocklisted key from its keychain. the key paid for its accounts, so their rent goes back to its wallet
+    pub fn force_remove_key(ctx: Context<ForceRemoveKey>, key: Pubkey) -> Result <()> {
+        let keychain = &mut ctx.accounts.keychain;
+        let keychain_state = &mut ctx.accounts.keychain_state;
+        let wallet = ctx.accounts.wallet.to_account_info();
+
+        ctx.accounts.keychain_key.close(wallet.clone())?;
+        ctx.accounts.domain_state.remove_key();
+        release_identity(keychain, keychain_state, &key,
+                         ctx.accounts.identity_mint.as_deref(),
+                         ctx.accounts.identity_token.as_deref(),
+                         ctx.accounts.token_program.as_ref())?;
+
+        // nobody left, so the keychain goes too (along with its log) - as long as its vault's empty
+        if keychain.num_keys == 1 {
+            msg!("Closing keychain: {}", keychain.key());
+            require!(ctx.accounts.vault.is_some(), KeychainError::MissingVaultAccount);
+            check_vault_empty(ctx.accounts.vault.as_ref().unwrap(), ctx.remaining_accounts)?;
+            ctx.accounts.domain_state.remove_keychain();
+            close_activity_log(keychain_state, ctx.accounts.activity_log.as_deref_mut(), wallet.clone())?;
+            keychain.close(wallet.clone())?;
+            keychain_state.close(wallet)?;
+            return Ok(());
+        }
+
+        keychain.remove_key(key);
+        log_activity(keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::ForceRemoveKey,
+                     ctx.accounts.authority.key(), key)?;
+
+        // removing the key shifts the vote indices, so votes on a pending action need to be cast again (and an action
+        // on the removed key itself is dropped). that includes an already approved merge or detach, which is only
+        // verified once the votes are in
+        if let Some(pending_action) = keychain_state.pending_action.as_mut() {
+            if pending_action.key == key {
+                keychain_state.pending_action = None;
+            } else {
+                pending_action.votes = SmallBitSet::default();
+                pending_action.rejections = SmallBitSet::default();
+                if matches!(pending_action.action_type, KeyChainActionType::Merge { .. } | KeyChainActionType::Detach { .. }) {
+                    pending_action.verified = false;
+                }
+            }
+        }
+
+        msg!("force removed key {} from keychain {}", key, keychain.key());
+        Ok(())
+    }
+
+    // for domains that were created before the registry existed
+    pub fn register_domain(ctx: Context<RegisterDomain>) -> Result <()> {
+        ctx.accounts.domain_registry.domains.push(ctx.accounts.domain.key());
+        msg!("registered domain account: {}", ctx.accounts.domain.key());
+        Ok(())
+    }
+
     // todo: make this callable by the domain admin. currently this is a super-admin function
 
     // just for closing the domain account
@@ -79,12 +228,8 @@ pub mod keychain {
     // if done by admin, then the authority needs to be the Domain's authority
     pub fn create_keychain(ctx: Context<CreateKeychain>, keychain_name: String) -> Result <()> {
 
-        // we only reserve 32 bytes for the name
-        require!(keychain_name.as_bytes().len() <= 32, KeychainError::NameTooLong);
-        require!(keychain_name.len() >= 3, KeychainError::NameTooShort);
-
-        let is_valid_name = is_valid_name(&keychain_name);
-        require!(is_valid_name, KeychainError::InvalidName);
+        // length + charset rules come from the domain
+        ctx.accounts.domain.check_name(&keychain_name)?;
 
         // if the signer is the same as the domain authority, then this is a domain admin
         // for now, don't allow this. this is for when a project wants to pre-allocate or create keychains on behalf of the user
@@ -98,28 +243,34 @@ pub mod keychain {
         // the wallet being added needs to be the signer (ignore admin scenario)
         require!(ctx.accounts.authority.key() == *ctx.accounts.wallet.key, KeychainError::NotSigner);
 
-        let keychain_state = &mut ctx.accounts.keychain_state;
-        keychain_state.keychain_version = CURRENT_KEYCHAIN_VERSION;
-        keychain_state.keychain = ctx.accounts.keychain.key();
-        keychain_state.action_threshold = ctx.accounts.domain.keychain_action_threshold;
+        let bump = *ctx.bumps.get("keychain").unwrap();
+        init_keychain(&mut ctx.accounts.keychain, &mut ctx.accounts.keychain_state, &mut ctx.accounts.keychain_key,
+                      &ctx.accounts.domain, keychain_name, ctx.accounts.wallet.key(), bump,
+                      *ctx.bumps.get("keychain_state").unwrap(), *ctx.bumps.get("keychain_key").unwrap())?;
 
-        let key = UserKey {
-            key: *ctx.accounts.wallet.to_account_info().key,
-        };
+        ctx.accounts.domain_state.add_keychain();
+        ctx.accounts.domain_state.add_key();
 
-        let keychain = &mut ctx.accounts.keychain;
-        keychain.name = keychain_name;
-        keychain.num_keys = 1;
-        keychain.domain = ctx.accounts.domain.name.clone();
-        keychain.bump = *ctx.bumps.get("keychain").unwrap();
-        keychain.keys = vec![key];
+        msg!("created keychain account: {}", ctx.accounts.keychain.key());
+        msg!("created key account: {}", ctx.accounts.keychain_key.key());
 
-        // now set up the pointer/map account
-        let keychain_key = &mut ctx.accounts.keychain_key;
-        keychain_key.key = ctx.accounts.wallet.key();
-        keychain_key.keychain = ctx.accounts.keychain.key();
+        Ok(())
+    }
 
-        msg!("created keychain account: {}", ctx.accounts.keychain.key());
+    // creates a keychain without a name - the pda is derived from the creating wallet, and the wallet's address is
+    // used as the keychain's name
+    pub fn create_wallet_keychain(ctx: Context<CreateWalletKeychain>) -> Result <()> {
+        let wallet = ctx.accounts.authority.key();
+
+        let bump = *ctx.bumps.get("keychain").unwrap();
+        init_keychain(&mut ctx.accounts.keychain, &mut ctx.accounts.keychain_state, &mut ctx.accounts.keychain_key,
+                      &ctx.accounts.domain, wallet.to_string(), wallet, bump,
+                      *ctx.bumps.get("keychain_state").unwrap(), *ctx.bumps.get("keychain_key").unwrap())?;
+
+        ctx.accounts.domain_state.add_keychain();
+        ctx.accounts.domain_state.add_key();
+
+        msg!("created wallet keychain account: {}", ctx.accounts.keychain.key());
         msg!("created key account: {}", ctx.accounts.keychain_key.key());
 
         Ok(())
@@ -132,12 +283,11 @@ pub mod keychain {
         let keychain_state = &mut ctx.accounts.keychain_state;
 
         // set to older version
-        keychain_state.keychain_version = CURRENT_KEYCHAIN_VERSION - 1;
+        keychain_state.keychain_version = 1;
         keychain_state.keychain = ctx.accounts.keychain.key();
+        keychain_state.bump = *ctx.bumps.get("keychain_state").unwrap();
 
-        let key = UserKey {
-            key: *ctx.accounts.wallet.to_account_info().key,
-        };
+        let key = UserKey::new(*ctx.accounts.wallet.to_account_info().key);
 
         let keychain = &mut ctx.accounts.keychain;
         keychain.num_keys = 1;
@@ -148,6 +298,7 @@ pub mod keychain {
         let keychain_key = &mut ctx.accounts.key;
         keychain_key.key = ctx.accounts.wallet.key();
         keychain_key.keychain = ctx.accounts.keychain.key();
+        // older key layout too (no bump or version) - gets upgraded with migrate_key
 
         msg!("created keychain account: {}", ctx.accounts.keychain.key());
         msg!("created key account: {}", ctx.accounts.key.key());
@@ -156,96 +307,539 @@ pub mod keychain {
 
     // user w/existing keychain (and verified key), adds a new (unverified) key
     pub fn add_key(ctx: Context<AddKey>, key: Pubkey) -> Result <()> {
-        let keychain = &mut ctx.accounts.keychain;
+        let signer = ctx.accounts.authority.key();
+        process_add_key(ctx, signer, key)
+    }
 
-        let signer = *ctx.accounts.authority.to_account_info().key;
+    // vote = true means confirm & vote = false means reject
+    pub fn vote_pending_action<'info>(ctx: Context<'_, '_, '_, 'info, VotePendingAction<'info>>, vote: bool) -> Result <()> {
+        let signer = ctx.accounts.authority.key();
+        process_vote(ctx, signer, vote)
+    }
+
+    // only called when pending action = addkey
+    // user verifies a new (unverified) key on a keychain - potentially becomes linked but based on votes
+    pub fn verify_key(ctx: Context<VerifyKey>) -> Result <()> {
+        let key = ctx.accounts.authority.key();
+        complete_verify(&mut ctx.accounts.keychain, &mut ctx.accounts.keychain_state, &mut ctx.accounts.keychain_key,
+                        &mut ctx.accounts.domain_state, key,
+                        &ctx.accounts.authority.to_account_info(), &ctx.accounts.treasury,
+                        &ctx.accounts.system_program.to_account_info(), ctx.accounts.domain.key_cost,
+                        *ctx.bumps.get("keychain_key").unwrap())?;
+        log_activity(&ctx.accounts.keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::VerifyKey, key, key)
+    }
 
-        require!(!keychain.has_key(&key), KeychainError::KeyAlreadyExists);
-        require!(usize::from(keychain.num_keys) < MAX_KEYS, KeychainError::MaxKeys);
+    // verify_key for a pda owned by another program (e.g. a game vault or guild): the owning program signs for the key
+    // with invoke_signed, and a separate payer covers the key account + key cost
+    pub fn verify_program_key(ctx: Context<VerifyProgramKey>) -> Result <()> {
+        let key = ctx.accounts.authority.key();
+        complete_verify(&mut ctx.accounts.keychain, &mut ctx.accounts.keychain_state, &mut ctx.accounts.keychain_key,
+                        &mut ctx.accounts.domain_state, key,
+                        &ctx.accounts.payer.to_account_info(), &ctx.accounts.treasury,
+                        &ctx.accounts.system_program.to_account_info(), ctx.accounts.domain.key_cost,
+                        *ctx.bumps.get("keychain_key").unwrap())?;
+        log_activity(&ctx.accounts.keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::VerifyKey, key, key)
+    }
+
+    // deposit sol or spl tokens (if the token accounts are given) into the keychain's vault
+    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result <()> {
+        require!(amount > 0, KeychainError::InvalidAmount);
+
+        if let Some(authority_token) = &ctx.accounts.authority_token {
+            require!(ctx.accounts.vault_token.is_some() && ctx.accounts.token_program.is_some(), KeychainError::MissingVaultAccount);
+            let vault_token = ctx.accounts.vault_token.as_ref().unwrap();
+            require!(vault_token.mint == authority_token.mint, KeychainError::InvalidVaultAccount);
+
+            let cpi_accounts = Transfer {
+                from: authority_token.to_account_info(),
+                to: vault_token.to_account_info(),
+                authority: ctx.accounts.authority.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new(ctx.accounts.token_program.as_ref().unwrap().to_account_info(), cpi_accounts);
+            token::transfer(cpi_ctx, amount)?;
+        } else {
+            invoke(
+                &system_instruction::transfer(
+                    ctx.accounts.authority.key,
+                    ctx.accounts.vault.key,
+                    amount,
+                ),
+                &[
+                    ctx.accounts.authority.to_account_info().clone(),
+                    ctx.accounts.vault.to_account_info().clone(),
+                    ctx.accounts.system_program.to_account_info().clone(),
+                ],
+            )?;
+        }
 
-        // check that there isn't already a pending action
+        Ok(())
+    }
+
+    // propose a withdrawal from the keychain's vault (mint = None for sol). destination is a wallet for sol or a token account for spl
+    pub fn withdraw(ctx: Context<Withdraw>, destination: Pubkey, mint: Option<Pubkey>, amount: u64) -> Result <()> {
+        require!(amount > 0, KeychainError::InvalidAmount);
+
+        let keychain = &ctx.accounts.keychain;
         let keychain_state = &mut ctx.accounts.keychain_state;
-        require!(keychain_state.pending_action.is_none(), KeychainError::PendingActionExists);
+        let signer = *ctx.accounts.authority.to_account_info().key;
+
+        // signer automatically casts vote to approve - gets executed in vote_pending_action once the threshold is reached
+        let mut pending_action = PendingKeyChainAction::new(KeyChainActionType::Withdraw { mint, amount }, destination);
+        let authority_index = keychain.index_of(&signer).unwrap() as u8;
+        pending_action.vote(authority_index, true);
+        keychain_state.pending_action = Some(pending_action);
+        keychain_state.record_activity()?;
+        log_activity(keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::Withdraw, signer, destination)?;
+
+        Ok(())
+    }
+
+    // propose an instruction for the keychain to sign (as the keychain pda, or the vault pda if use_vault). it gets
+    // invoked in vote_pending_action once the threshold is reached
+    pub fn propose_instruction(ctx: Context<ProposeInstruction>, target_program: Pubkey, metas: Vec<ProposedAccountMeta>, data: Vec<u8>, use_vault: bool) -> Result <()> {
+        // don't let proposals call back into the keychain program
+        require!(target_program != crate::ID, KeychainError::InvalidProposal);
+
+        let keychain = &ctx.accounts.keychain;
+        let signer = *ctx.accounts.authority.to_account_info().key;
+
+        // only the keychain + vault pdas get signed for. any other signer would be whoever casts the deciding vote (or
+        // relays it), and the target program would get their signature
+        let (vault, _) = Pubkey::find_program_address(&[
+            keychain.key().as_ref(), VAULT_SPACE.as_bytes(), keychain.domain.as_bytes(), KEYCHAIN.as_bytes(),
+        ], &crate::ID);
+        require!(metas.iter().all(|meta| !meta.is_signer || meta.pubkey == keychain.key() || meta.pubkey == vault), KeychainError::InvalidProposal);
+
+        // the keychain pda is the identity nft's freeze authority (and its holder's delegate), so proposals can't touch it -
+        // whatever the target program is, since it could cpi into the token program with the keychain's signature
+        let (identity_mint, _) = Pubkey::find_program_address(&[
+            keychain.key().as_ref(), IDENTITY_SPACE.as_bytes(), keychain.domain.as_bytes(), KEYCHAIN.as_bytes(),
+        ], &crate::ID);
+        let identity_token = ctx.accounts.keychain_state.identity_holder
+            .map(|holder| get_associated_token_address(&holder, &identity_mint));
+        require!(!metas.iter().any(|meta| meta.pubkey == identity_mint || Some(meta.pubkey) == identity_token), KeychainError::InvalidProposal);
+
+        let proposal = &mut ctx.accounts.proposal;
+        proposal.keychain = keychain.key();
+        proposal.proposer = signer;
+        proposal.program_id = target_program;
+        proposal.accounts = metas;
+        proposal.data = data;
+        proposal.use_vault = use_vault;
 
         // signer automatically casts vote to approve
-        let mut pending_action = PendingKeyChainAction::new(KeyChainActionType::AddKey, key);
+        let proposal_key = proposal.key();
+        let mut pending_action = PendingKeyChainAction::new(KeyChainActionType::Execute, proposal_key);
         let authority_index = keychain.index_of(&signer).unwrap() as u8;
-        pending_action.votes.set_index(authority_index);
+        pending_action.vote(authority_index, true);
+        ctx.accounts.keychain_state.pending_action = Some(pending_action);
+        ctx.accounts.keychain_state.record_activity()?;
+        log_activity(&ctx.accounts.keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::ProposeInstruction, signer, proposal_key)?;
 
-        keychain_state.pending_action = Some(pending_action);
+        Ok(())
+    }
 
-        // don't even bother checking the threshold cause let's not ever allow just 1 vote to add a key
+    // propose changing the vote weight of a key on the keychain
+    pub fn set_key_weight(ctx: Context<SetKeyWeight>, key: Pubkey, weight: u8) -> Result <()> {
+        require!(weight > 0, KeychainError::InvalidWeight);
 
-        // todo: MIGHT wanna add the key account as an optional to mae sure it doesn't exist yet: https://solana.stackexchange.com/questions/3745/anchors-init-if-constraint-for-the-optional-initialization-of-accounts
+        let keychain = &ctx.accounts.keychain;
+        let signer = *ctx.accounts.authority.to_account_info().key;
+
+        // signer automatically casts vote to approve
+        let mut pending_action = PendingKeyChainAction::new(KeyChainActionType::SetWeight { weight }, key);
+        let authority_index = keychain.index_of(&signer).unwrap() as u8;
+        pending_action.vote(authority_index, true);
+        ctx.accounts.keychain_state.pending_action = Some(pending_action);
+        ctx.accounts.keychain_state.record_activity()?;
+        log_activity(&ctx.accounts.keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::SetKeyWeight, signer, key)?;
 
         Ok(())
     }
 
-    // vote = true means confirm & vote = false means reject
-    pub fn vote_pending_action(ctx: Context<VotePendingAction>, vote: bool) -> Result <()> {
-        let keychain = &mut ctx.accounts.keychain;
+    // propose changing the keychain's action threshold, in total key weight (0 = all keys)
+    pub fn set_action_threshold(ctx: Context<SetActionThreshold>, threshold: u8) -> Result <()> {
+        let keychain = &ctx.accounts.keychain;
         let signer = *ctx.accounts.authority.to_account_info().key;
 
-        // a single rejection will cancel the pending action
-        if !vote {
-            // clear the pending action
-            ctx.accounts.keychain_state.pending_action = None;
-        } else {
-            set_vote(keychain, &mut ctx.accounts.keychain_state, &signer, vote);
-
-            let action_threshold = ctx.accounts.keychain_state.action_threshold;
-            let pending_action = ctx.accounts.keychain_state.pending_action.as_mut().unwrap();
-
-            if (action_threshold > 0 && pending_action.votes.count_set() >= action_threshold) ||
-                (u16::from(pending_action.votes.count_set()) == keychain.num_keys) {
-
-                // perform the pending action
-                match pending_action.action_type {
-                    KeyChainActionType::AddKey => {
-                        // this would require us to verify first
-                        // require!(pending_action.verified, KeychainError::KeyNotVerified);
-
-                        // if the key has already been verified, then we add it to the keychain and clear the pending action
-                        if pending_action.verified {
-                            keychain.add_key(pending_action.key);
-                            ctx.accounts.keychain_state.pending_action = None;
-                        }
-                    },
-                    KeyChainActionType::RemoveKey => {
-                        // remove the key - in this case we need to have been passed in the keychain_key account
-                        require!(ctx.accounts.keychain_key.is_some(), KeychainError::MissingKeyAccount);
-                        keychain.remove_key(pending_action.key);
-
-                        // close the keychain_key account - send lamports back to the signer
-                        let keychain_key = ctx.accounts.keychain_key.as_mut().unwrap();
-                        keychain_key.close(ctx.accounts.authority.to_account_info())?;
-                        // clear the pending action
-                        ctx.accounts.keychain_state.pending_action = None;
-                    },
+        // signer automatically casts vote to approve
+        let keychain_key = keychain.key();
+        let mut pending_action = PendingKeyChainAction::new(KeyChainActionType::SetThreshold { threshold }, keychain_key);
+        let authority_index = keychain.index_of(&signer).unwrap() as u8;
+        pending_action.vote(authority_index, true);
+        ctx.accounts.keychain_state.pending_action = Some(pending_action);
+        ctx.accounts.keychain_state.record_activity()?;
+        log_activity(&ctx.accounts.keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::SetActionThreshold, signer, keychain_key)?;
+
+        Ok(())
+    }
+
+    // upgrades a v2 keychain to the current layout (keys get the default weight), reallocing its accounts if the new
+    // layout needs more space. anyone can call this - the payer covers any extra rent
+    pub fn upgrade_keychain(ctx: Context<UpgradeKeychain>) -> Result <()> {
+        let keychain_info = ctx.accounts.keychain.to_account_info();
+        let keychain_state_info = ctx.accounts.keychain_state.to_account_info();
+        let payer = ctx.accounts.payer.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+
+        let old_state = {
+            let data = keychain_state_info.try_borrow_data()?;
+            require!(data[..8] == KeyChainState::discriminator(), KeychainError::InvalidKeychainVersion);
+            let mut reader: &[u8] = &data[8..];
+            match data[8] {
+                // v11 added the activity log flag, v10 the bump (which gets found again below)
+                9 | 10 => KeyChainStateV9::deserialize(&mut reader)?,
+                // v9 added the identity holder, v7 added intent nonces
+                7 | 8 => KeyChainStateV8::deserialize(&mut reader)?.into(),
+                // v6 added inheritance
+                6 => KeyChainStateV9::from(KeyChainStateV8::from(KeyChainStateV6::deserialize(&mut reader)?)),
+                // v5 added rejections to the pending action
+                5 => KeyChainStateV9::from(KeyChainStateV8::from(KeyChainStateV6::from(KeyChainStateV5::deserialize(&mut reader)?))),
+                _ => {
+                    let old_state = KeyChainStateV3::deserialize(&mut reader)?;
+                    // v4 added the display name
+                    let display_name = if old_state.keychain_version == 4 { Option::<String>::deserialize(&mut reader)? } else { None };
+                    KeyChainStateV9::from(KeyChainStateV8::from(KeyChainStateV6::from(KeyChainStateV5::from_v3(old_state, display_name))))
                 }
             }
+        };
+        require!(old_state.keychain == keychain_info.key(), KeychainError::InvalidKeychainVersion);
+        require!((2..CURRENT_KEYCHAIN_VERSION).contains(&old_state.keychain_version), KeychainError::InvalidKeychainVersion);
+
+        // v2 keychains don't have key weights, and nothing before v8 has the program-controlled flag
+        if old_state.keychain_version < 8 {
+            let keychain = {
+                let data = keychain_info.try_borrow_data()?;
+                require!(data[..8] == CurrentKeyChain::discriminator(), KeychainError::InvalidKeychainVersion);
+                if old_state.keychain_version == 2 {
+                    let old_keychain = KeyChainV2::deserialize(&mut &data[8..])?;
+                    CurrentKeyChain {
+                        name: old_keychain.name,
+                        num_keys: old_keychain.num_keys,
+                        domain: old_keychain.domain,
+                        bump: old_keychain.bump,
+                        keys: old_keychain.keys.into_iter().map(UserKey::new).collect(),
+                    }
+                } else {
+                    let old_keychain = KeyChainV7::deserialize(&mut &data[8..])?;
+                    CurrentKeyChain {
+                        name: old_keychain.name,
+                        num_keys: old_keychain.num_keys,
+                        domain: old_keychain.domain,
+                        bump: old_keychain.bump,
+                        keys: old_keychain.keys.into_iter().map(|key| UserKey { weight: key.weight, ..UserKey::new(key.key) }).collect(),
+                    }
+                }
+            };
+
+            grow_account(&keychain_info, &payer, &system_program, 8 + CurrentKeyChain::MAX_SIZE)?;
+            let mut data = keychain_info.try_borrow_mut_data()?;
+            let mut writer: &mut [u8] = &mut data[8..];
+            keychain.serialize(&mut writer)?;
         }
 
+        // v10 stores the state's bump, so this is the last time it needs to be found
+        let domain = CurrentKeyChain::try_deserialize(&mut &keychain_info.try_borrow_data()?[..])?.domain;
+        let (state_address, bump) = Pubkey::find_program_address(
+            &[keychain_info.key.as_ref(), KEYCHAIN_STATE_SPACE.as_bytes(), domain.as_bytes(), KEYCHAIN.as_bytes()],
+            &crate::ID,
+        );
+        require!(state_address == keychain_state_info.key(), KeychainError::InvalidKeychainVersion);
+
+        let keychain_state = KeyChainState {
+            keychain_version: CURRENT_KEYCHAIN_VERSION,
+            last_active: Clock::get()?.unix_timestamp,
+            ..KeyChainState::from_v9(old_state, bump)
+        };
+
+        // the bytes past the old layout can be left over from earlier (larger) pending actions, so clear them out
+        grow_account(&keychain_state_info, &payer, &system_program, 8 + KeyChainState::MAX_SIZE)?;
+        let mut data = keychain_state_info.try_borrow_mut_data()?;
+        data[8..].fill(0);
+        let mut writer: &mut [u8] = &mut data[8..];
+        keychain_state.serialize(&mut writer)?;
+
+        msg!("upgraded keychain {} to version {}", keychain_info.key(), CURRENT_KEYCHAIN_VERSION);
         Ok(())
     }
 
-    // only called when pending action = addkey
-    // user verifies a new (unverified) key on a keychain - potentially becomes linked but based on votes
-    pub fn verify_key(ctx: Context<VerifyKey>) -> Result <()> {
-        let keychain = &mut ctx.accounts.keychain;
-        let domain = &ctx.accounts.domain;
+    // upgrades a key account to the current version (reallocing it if the new layout needs more space). anyone can call
+    // this - the payer covers any extra rent
+    pub fn migrate_key(ctx: Context<MigrateKey>) -> Result <()> {
+        let keychain_key_info = ctx.accounts.keychain_key.to_account_info();
+
+        let old_key = {
+            let data = keychain_key_info.try_borrow_data()?;
+            require!(data[..8] == KeyChainKey::discriminator(), KeychainError::InvalidKeyAccount);
+            // older key accounts are either too small to have a version or have 0 there
+            require!(data.get(KeyChainKey::VERSION_OFFSET).copied().unwrap_or(0) < CURRENT_KEY_VERSION, KeychainError::InvalidKeychainVersion);
+            // v1 added the bump + version (later versions will need to match on the version here)
+            KeyChainKeyV0::deserialize(&mut &data[8..])?
+        };
+
+        let keychain_key = KeyChainKey {
+            keychain: old_key.keychain,
+            key: old_key.key,
+            bump: *ctx.bumps.get("keychain_key").unwrap(),
+            version: CURRENT_KEY_VERSION,
+        };
+
+        grow_account(&keychain_key_info, &ctx.accounts.payer.to_account_info(), &ctx.accounts.system_program.to_account_info(),
+                     8 + KeyChainKey::SIZE)?;
+        {
+            let mut data = keychain_key_info.try_borrow_mut_data()?;
+            let mut writer: &mut [u8] = &mut data[8..];
+            keychain_key.serialize(&mut writer)?;
+        }
+
+        msg!("migrated key account {} to version {}", keychain_key_info.key(), CURRENT_KEY_VERSION);
+        Ok(())
+    }
+
+    // upgrades a domain state to the current version (reallocing it if the new layout needs more space). anyone can call
+    // this - the payer covers any extra rent
+    pub fn migrate_domain_state(ctx: Context<MigrateDomainState>) -> Result <()> {
+        let domain_state_info = ctx.accounts.domain_state.to_account_info();
+
+        let old_state = {
+            let data = domain_state_info.try_borrow_data()?;
+            require!(data[..8] == DomainState::discriminator(), KeychainError::InvalidKeychainVersion);
+            require!(data[8] < CURRENT_DOMAIN_VERSION, KeychainError::InvalidKeychainVersion);
+            // v2 added the bump, v3 the stats - both come after the domain
+            DomainStateV1::deserialize(&mut &data[8..])?
+        };
+
+        let domain_state = DomainState {
+            version: CURRENT_DOMAIN_VERSION,
+            domain: old_state.domain,
+            bump: *ctx.bumps.get("domain_state").unwrap(),
+            // the stats can't be recovered on-chain, so they start from here
+            num_keychains: 0,
+            num_keys: 0,
+            total_fees_collected: 0,
+            created_at: 0,
+        };
+
+        grow_account(&domain_state_info, &ctx.accounts.payer.to_account_info(), &ctx.accounts.system_program.to_account_info(),
+                     8 + DomainState::MAX_SIZE)?;
+        {
+            let mut data = domain_state_info.try_borrow_mut_data()?;
+            let mut writer: &mut [u8] = &mut data[8..];
+            domain_state.serialize(&mut writer)?;
+        }
+
+        msg!("migrated domain state {} to version {}", domain_state_info.key(), CURRENT_DOMAIN_VERSION);
+        Ok(())
+    }
+
+    // proposes merging this keychain into another (into = true) or another keychain into this one. both keychains need
+    // to propose + approve the merge before it can be executed
+    pub fn propose_merge(ctx: Context<ProposeMerge>, into: bool) -> Result <()> {
+        let keychain = &ctx.accounts.keychain;
+        let other_keychain = &ctx.accounts.other_keychain;
+        require!(usize::from(keychain.num_keys + other_keychain.num_keys) <= ctx.accounts.domain.key_limit(), KeychainError::MaxKeys);
+
+        // signer automatically casts vote to approve
+        let mut pending_action = PendingKeyChainAction::new(KeyChainActionType::Merge { into }, other_keychain.key());
+        let authority_index = keychain.index_of(&ctx.accounts.authority.key()).unwrap() as u8;
+        pending_action.vote(authority_index, true);
+        if keychain.threshold_reached(&pending_action.votes, ctx.accounts.keychain_state.action_threshold) {
+            pending_action.verify();
+        }
+        ctx.accounts.keychain_state.pending_action = Some(pending_action);
+        ctx.accounts.keychain_state.record_activity()?;
+        log_activity(&ctx.accounts.keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::ProposeMerge,
+                     ctx.accounts.authority.key(), other_keychain.key())?;
+
+        Ok(())
+    }
+
+    // moves the source keychain's keys into the target, re-pointing their key accounts (passed in remaining accounts, in
+    // the same order as the source's keys, followed by the source vault's token accounts), then closes the source keychain
+    pub fn merge_keychains<'info>(ctx: Context<'_, '_, '_, 'info, MergeKeychains<'info>>) -> Result <()> {
+        let source = &ctx.accounts.source;
+        let target = &mut ctx.accounts.target;
+        require!(usize::from(source.num_keys + target.num_keys) <= ctx.accounts.domain.key_limit(), KeychainError::MaxKeys);
+        require!(ctx.remaining_accounts.len() >= source.keys.len(), KeychainError::MissingKeyAccount);
+        // the source's log gets closed with it
+        require!(!ctx.accounts.source_state.has_activity_log || ctx.accounts.source_activity_log.is_some(), KeychainError::MissingActivityLog);
+
+        // the source's vault has to be emptied first, otherwise its funds would be stranded
+        let (key_accounts, vault_tokens) = ctx.remaining_accounts.split_at(source.keys.len());
+        check_vault_empty(&ctx.accounts.source_vault, vault_tokens)?;
+
+        for (user_key, account_info) in source.keys.iter().zip(key_accounts.iter()) {
+            let mut keychain_key: Account<CurrentKeyChainKey> = Account::try_from(account_info)?;
+            require!(keychain_key.key == user_key.key && keychain_key.keychain == source.key(), KeychainError::InvalidKeyAccount);
+            keychain_key.check_version()?;
+            keychain_key.keychain = target.key();
+            keychain_key.exit(&crate::ID)?;
+        }
+
+        // the source's identity nft doesn't carry over to the target
+        if let Some(holder) = ctx.accounts.source_state.identity_holder {
+            release_identity(source, &mut ctx.accounts.source_state, &holder,
+                             ctx.accounts.source_identity_mint.as_deref(),
+                             ctx.accounts.source_identity_token.as_deref(),
+                             ctx.accounts.token_program.as_ref())?;
+        }
+
+        target.merge_keys(&source.keys);
+        ctx.accounts.domain_state.remove_keychain();
+        ctx.accounts.target_state.pending_action = None;
+        ctx.accounts.target_state.record_activity()?;
+        log_activity(&ctx.accounts.target_state, ctx.accounts.target_activity_log.as_deref_mut(), ActivityType::Merge,
+                     ctx.accounts.authority.key(), source.key())?;
+
+        msg!("merged keychain {} into {}", source.key(), target.key());
+        Ok(())
+    }
+
+    // proposes moving a key off this keychain into a new keychain of its own, under the given name
+    pub fn propose_detach(ctx: Context<ProposeDetach>, key: Pubkey, name: String) -> Result <()> {
+        ctx.accounts.domain.check_name(&name)?;
+
+        let keychain = &ctx.accounts.keychain;
+        let signer = *ctx.accounts.authority.to_account_info().key;
+
+        // signer automatically casts vote to approve
+        let mut pending_action = PendingKeyChainAction::new(KeyChainActionType::Detach { name }, key);
+        let authority_index = keychain.index_of(&signer).unwrap() as u8;
+        pending_action.vote(authority_index, true);
+        if keychain.threshold_reached(&pending_action.votes, ctx.accounts.keychain_state.action_threshold) {
+            pending_action.verify();
+        }
+        ctx.accounts.keychain_state.pending_action = Some(pending_action);
+        ctx.accounts.keychain_state.record_activity()?;
+        log_activity(&ctx.accounts.keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::ProposeDetach, signer, key)?;
+
+        Ok(())
+    }
+
+    // executes an approved detach: the key moves to a new keychain (as its only key) and its key account gets re-pointed
+    pub fn detach_key(ctx: Context<DetachKey>, name: String) -> Result <()> {
+        let key = ctx.accounts.keychain_key.key;
+
+        ctx.accounts.keychain.remove_key(key);
+        release_identity(&ctx.accounts.keychain, &mut ctx.accounts.keychain_state, &key,
+                         ctx.accounts.identity_mint.as_deref(),
+                         ctx.accounts.identity_token.as_deref(),
+                         ctx.accounts.token_program.as_ref())?;
+        ctx.accounts.keychain_state.pending_action = None;
+        ctx.accounts.keychain_state.record_activity()?;
+        log_activity(&ctx.accounts.keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::Detach,
+                     ctx.accounts.authority.key(), key)?;
+
+        let bump = *ctx.bumps.get("new_keychain").unwrap();
+        let key_bump = ctx.accounts.keychain_key.bump;
+        init_keychain(&mut ctx.accounts.new_keychain, &mut ctx.accounts.new_keychain_state, &mut ctx.accounts.keychain_key,
+                      &ctx.accounts.domain, name, key, bump, *ctx.bumps.get("new_keychain_state").unwrap(), key_bump)?;
+        // the key just moves, so only the keychain count changes
+        ctx.accounts.domain_state.add_keychain();
+
+        msg!("detached key {} from {} to new keychain {}", key, ctx.accounts.keychain.key(), ctx.accounts.new_keychain.key());
+        Ok(())
+    }
+
+    // takes back the signer's vote (approval or rejection) without vetoing the pending action
+    pub fn retract_vote(ctx: Context<RetractVote>) -> Result <()> {
+        let index = ctx.accounts.keychain.index_of(&ctx.accounts.authority.key()).unwrap() as u8;
+        let pending_action = ctx.accounts.keychain_state.pending_action.as_mut().unwrap();
+        require!(pending_action.votes.is_set(index) || pending_action.rejections.is_set(index), KeychainError::VoteNotFound);
+
+        pending_action.votes.unset_index(index);
+        pending_action.rejections.unset_index(index);
+        let target = pending_action.key;
+        ctx.accounts.keychain_state.record_activity()?;
+        log_activity(&ctx.accounts.keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::RetractVote,
+                     ctx.accounts.authority.key(), target)
+    }
+
+    // closes a key account that points to a keychain the key isn't on (and isn't pending on), so the wallet can be
+    // linked again. the rent (+ any held key cost) goes back to the wallet, so anyone can call this
+    pub fn close_orphaned_key(ctx: Context<CloseOrphanedKey>) -> Result <()> {
+        let keychain_info = ctx.accounts.keychain.to_account_info();
+        let key = ctx.accounts.keychain_key.key;
+
+        // a closed keychain orphans all its keys
+        if keychain_info.owner == &crate::ID && !keychain_info.data_is_empty() {
+            let keychain = CurrentKeyChain::try_deserialize(&mut &keychain_info.try_borrow_data()?[..])?;
+            require!(ctx.accounts.keychain_state.is_some(), KeychainError::MissingKeyAccount);
+            let keychain_state = ctx.accounts.keychain_state.as_ref().unwrap();
+            require!(!keychain.has_key(&key) && !keychain_state.has_pending_action_key(&key), KeychainError::KeyNotOrphaned);
+        }
+
+        ctx.accounts.keychain_key.close(ctx.accounts.wallet.to_account_info())?;
+        msg!("closed orphaned key account for {}", key);
+        Ok(())
+    }
+
+    // any key on the keychain can set (or clear) the display name
+    pub fn update_display_name(ctx: Context<UpdateDisplayName>, display_name: Option<String>) -> Result <()> {
+        if let Some(display_name) = &display_name {
+            check_display_name(display_name)?;
+        }
+        ctx.accounts.keychain_state.display_name = display_name;
+        ctx.accounts.keychain_state.record_activity()?;
+        log_activity(&ctx.accounts.keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::UpdateDisplayName,
+                     ctx.accounts.authority.key(), ctx.accounts.keychain.key())
+    }
+
+    // propose setting (or clearing) the wallet that can claim the keychain once it's been inactive for inactivity_period seconds
+    pub fn set_beneficiary(ctx: Context<SetBeneficiary>, beneficiary: Option<Pubkey>, inactivity_period: i64) -> Result <()> {
+        let keychain = &ctx.accounts.keychain;
         let signer = *ctx.accounts.authority.to_account_info().key;
 
-        // check that the payer can pay for this
-        if ctx.accounts.authority.lamports() < domain.key_cost {
-            return Err(KeychainError::NotEnoughSol.into());
+        if let Some(beneficiary) = beneficiary {
+            require!(inactivity_period > 0, KeychainError::InvalidInactivityPeriod);
+            require!(!keychain.has_key(&beneficiary), KeychainError::KeyAlreadyExists);
         }
 
-        // pay for this key - transfer sol to treasury
+        // signer automatically casts vote to approve
+        let mut pending_action = PendingKeyChainAction::new(KeyChainActionType::SetBeneficiary { beneficiary, inactivity_period }, keychain.key());
+        let authority_index = keychain.index_of(&signer).unwrap() as u8;
+        pending_action.vote(authority_index, true);
+        ctx.accounts.keychain_state.pending_action = Some(pending_action);
+        ctx.accounts.keychain_state.record_activity()?;
+        log_activity(&ctx.accounts.keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::SetBeneficiary, signer, keychain.key())?;
+
+        Ok(())
+    }
+
+    // lets a key show it's still around without doing anything else
+    pub fn heartbeat(ctx: Context<Heartbeat>) -> Result <()> {
+        ctx.accounts.keychain_state.record_activity()
+    }
+
+    // any key on the keychain can start its activity log. from then on, the instructions that change the keychain
+    // need the log passed in and record to it (heartbeats and deposits don't)
+    pub fn create_activity_log(ctx: Context<CreateActivityLog>) -> Result <()> {
+        let activity_log = &mut ctx.accounts.activity_log;
+        activity_log.keychain = ctx.accounts.keychain.key();
+        activity_log.bump = *ctx.bumps.get("activity_log").unwrap();
+
+        let keychain_state = &mut ctx.accounts.keychain_state;
+        keychain_state.has_activity_log = true;
+        keychain_state.record_activity()?;
+        log_activity(keychain_state, Some(activity_log), ActivityType::CreateActivityLog,
+                     ctx.accounts.authority.key(), ctx.accounts.keychain.key())
+    }
+
+    // once the keychain's been inactive for its inactivity period, anyone can add the beneficiary's key to it (paying for
+    // the key). the beneficiary gets enough weight to act on its own, since the other keys are presumably gone
+    pub fn claim_keychain(ctx: Context<ClaimKeychain>) -> Result <()> {
+        let keychain = &mut ctx.accounts.keychain;
+        let keychain_state = &mut ctx.accounts.keychain_state;
+        let domain = &ctx.accounts.domain;
+        let beneficiary = ctx.accounts.beneficiary.key();
+
+        require!(keychain_state.is_claimable(Clock::get()?.unix_timestamp), KeychainError::KeychainStillActive);
+        require!(usize::from(keychain.num_keys) < domain.key_limit(), KeychainError::MaxKeys);
+
         invoke(
             &system_instruction::transfer(
                 ctx.accounts.authority.key,
-                &domain.treasury,
+                ctx.accounts.treasury.key,
                 domain.key_cost,
             ),
             &[
@@ -255,58 +849,178 @@ pub mod keychain {
             ],
         )?;
 
-        // set up the pointer/map account
         let keychain_key = &mut ctx.accounts.keychain_key;
-        keychain_key.key = ctx.accounts.authority.key();
+        keychain_key.key = beneficiary;
         keychain_key.keychain = keychain.key();
+        keychain_key.bump = *ctx.bumps.get("keychain_key").unwrap();
+        keychain_key.version = CURRENT_KEY_VERSION;
 
-        let action_threshold = ctx.accounts.keychain_state.action_threshold;
-        let pending_action = ctx.accounts.keychain_state.pending_action.as_mut().unwrap();
-
-        // either we've hit the threshold or all keys have voted
-        if (action_threshold > 0 && pending_action.count_votes() >= action_threshold) ||
-            (u16::from(pending_action.count_votes()) == keychain.num_keys) {
-
-            // we've reached the threshold - remove the pending action
-            let keychain_state = &mut ctx.accounts.keychain_state;
-            // clear pending action
-            keychain_state.pending_action = None;
-
-            // Add it to the keychain.
-            keychain.add_key(signer);
-        } else {
-            // then we haven't reached the threshold yet - but make sure we've set the verified
-            pending_action.verified = true;
+        // an "all keys" threshold becomes the total weight of the existing keys, which the beneficiary then matches
+        if keychain_state.action_threshold == 0 {
+            let total_weight: u16 = keychain.keys.iter().map(|k| u16::from(k.weight)).sum();
+            keychain_state.action_threshold = total_weight.min(u16::from(u8::MAX)) as u8;
         }
+        keychain.add_key(beneficiary);
+        keychain.get_key(&beneficiary).unwrap().weight = keychain_state.action_threshold.max(DEFAULT_KEY_WEIGHT);
+        ctx.accounts.domain_state.collect_fee(domain.key_cost);
+        ctx.accounts.domain_state.add_key();
 
+        keychain_state.beneficiary = None;
+        keychain_state.inactivity_period = 0;
+        keychain_state.record_activity()?;
+        log_activity(keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::Claim, ctx.accounts.authority.key(), beneficiary)?;
+
+        msg!("beneficiary {} claimed keychain {}", beneficiary, keychain.key());
         Ok(())
     }
 
     // remove a key from a keychain
     pub fn remove_key(ctx: Context<RemoveKey>, key: Pubkey) -> Result <()> {
-        let keychain = &mut ctx.accounts.keychain;
-        let keychain_state = &mut ctx.accounts.keychain_state;
-        let signer = *ctx.accounts.authority.to_account_info().key;
+        let signer = ctx.accounts.authority.key();
+        process_remove_key(ctx, signer, key)
+    }
 
-        // if this is the only linked key, then we close the whole keychain
-        if keychain.num_keys == 1 {
-            msg!("Closing keychain: {}", keychain.key());
-            // close the keychain
-            keychain.close(ctx.accounts.authority.to_account_info())?;
-            keychain_state.close(ctx.accounts.authority.to_account_info())?;
-            let keychain_key = &mut ctx.accounts.keychain_key;
-            keychain_key.close(ctx.accounts.authority.to_account_info())?;
+    // the relay_ instructions take the same args plus an intent signed by a key on the keychain (checked by the ed25519
+    // program in the preceding instruction), so a relayer can submit + pay for them as the authority
+    pub fn relay_add_key(ctx: Context<AddKey>, key: Pubkey, intent: Intent) -> Result <()> {
+        require!(intent.action == IntentAction::AddKey { key }, KeychainError::InvalidIntent);
+        let signer = relay_intent(ctx.accounts.instructions.as_ref(), &ctx.accounts.keychain, &mut ctx.accounts.keychain_state, &intent)?;
+        process_add_key(ctx, signer, key)
+    }
 
-        } else {
-            // votes
-            let mut pending_action = PendingKeyChainAction::new(KeyChainActionType::RemoveKey, key);
-            let authority_index = keychain.index_of(&signer).unwrap() as u8;
-            pending_action.vote(authority_index, true);
-            keychain_state.pending_action = Some(pending_action);
+    pub fn relay_vote<'info>(ctx: Context<'_, '_, '_, 'info, VotePendingAction<'info>>, vote: bool, intent: Intent) -> Result <()> {
+        let pending_action = ctx.accounts.keychain_state.pending_action.as_ref().unwrap();
+        let action = IntentAction::Vote { vote, action_type: pending_action.action_type.clone(), key: pending_action.key };
+        require!(intent.action == action, KeychainError::InvalidIntent);
+        let signer = relay_intent(ctx.accounts.instructions.as_ref(), &ctx.accounts.keychain, &mut ctx.accounts.keychain_state, &intent)?;
+        process_vote(ctx, signer, vote)
+    }
+
+    pub fn relay_remove_key(ctx: Context<RemoveKey>, key: Pubkey, intent: Intent) -> Result <()> {
+        require!(intent.action == IntentAction::RemoveKey { key }, KeychainError::InvalidIntent);
+        let signer = relay_intent(ctx.accounts.instructions.as_ref(), &ctx.accounts.keychain, &mut ctx.accounts.keychain_state, &intent)?;
+        process_remove_key(ctx, signer, key)
+    }
+
+    // proves how much of a mint (or how many nfts of a verified collection) a keychain holds across its keys' token
+    // accounts, passed in remaining accounts (see keychain_holdings). the total is returned
+    pub fn get_holdings<'info>(ctx: Context<'_, '_, '_, 'info, GetHoldings<'info>>, mint: Pubkey, collection: bool) -> Result <u64> {
+        let total = keychain_holdings(&ctx.accounts.keychain, ctx.remaining_accounts, &mint, collection)?;
+        msg!("keychain {} holds {} of {}", ctx.accounts.keychain.key(), total, mint);
+        Ok(total)
+    }
+
+    // mints the keychain's identity nft (non-transferable: frozen, with the keychain pda as mint + freeze authority) to
+    // the signing key. minting to a different key burns the current holder's, and the nft gets burned when its holder
+    // is removed from the keychain
+    pub fn mint_identity(ctx: Context<MintIdentity>, uri: String) -> Result <()> {
+        let keychain = &ctx.accounts.keychain;
+        let authority = ctx.accounts.authority.key();
+        let token_program = ctx.accounts.token_program.to_account_info();
+        let identity_mint = ctx.accounts.identity_mint.to_account_info();
+
+        if let Some(holder) = ctx.accounts.keychain_state.identity_holder {
+            require!(holder != authority, KeychainError::IdentityAlreadyHeld);
+            require!(ctx.accounts.previous_token.is_some(), KeychainError::MissingIdentityAccount);
+            let previous_token = ctx.accounts.previous_token.as_ref().unwrap();
+            require!(previous_token.owner == holder, KeychainError::InvalidIdentityAccount);
+            burn_identity(keychain, &identity_mint, previous_token, &token_program)?;
+        }
+
+        let name_seed = CurrentKeyChain::name_seed(&keychain.name);
+        let seeds = &[
+            name_seed.as_ref(),
+            KEYCHAIN_SPACE.as_bytes().as_ref(),
+            keychain.domain.as_bytes().as_ref(),
+            KEYCHAIN.as_bytes().as_ref(),
+            &[keychain.bump],
+        ];
+        let signer = &[&seeds[..]];
+        let identity_token = ctx.accounts.identity_token.to_account_info();
+
+        // a holder that left before every way off the keychain burned the nft can still have a frozen (empty) account
+        if ctx.accounts.identity_token.is_frozen() {
+            token::thaw_account(CpiContext::new_with_signer(token_program.clone(), ThawAccount {
+                account: identity_token.clone(),
+                mint: identity_mint.clone(),
+                authority: keychain.to_account_info(),
+            }, signer))?;
+        }
+        token::mint_to(CpiContext::new_with_signer(token_program.clone(), MintTo {
+            mint: identity_mint.clone(),
+            to: identity_token.clone(),
+            authority: keychain.to_account_info(),
+        }, signer), 1)?;
+
+        // the keychain becomes the delegate so it can burn the nft later without the holder, then it's frozen in place
+        token::approve(CpiContext::new(token_program.clone(), Approve {
+            to: identity_token.clone(),
+            delegate: keychain.to_account_info(),
+            authority: ctx.accounts.authority.to_account_info(),
+        }), 1)?;
+        token::freeze_account(CpiContext::new_with_signer(token_program.clone(), FreezeAccount {
+            account: identity_token,
+            mint: identity_mint.clone(),
+            authority: keychain.to_account_info(),
+        }, signer))?;
+
+        // metadata only gets created on the first mint: the keychain name + domain (as the symbol)
+        if ctx.accounts.metadata.data_is_empty() {
+            let name: String = keychain.name.chars().take(mpl_token_metadata::state::MAX_NAME_LENGTH).collect();
+            let symbol: String = keychain.domain.chars().take(mpl_token_metadata::state::MAX_SYMBOL_LENGTH).collect();
+            invoke_signed(
+                &create_metadata_accounts_v3(
+                    mpl_token_metadata::ID,
+                    ctx.accounts.metadata.key(),
+                    identity_mint.key(),
+                    keychain.key(),
+                    authority,
+                    keychain.key(),
+                    name,
+                    symbol,
+                    uri,
+                    None,
+                    0,
+                    true,
+                    true,
+                    None,
+                    None,
+                    None,
+                ),
+                &[
+                    ctx.accounts.metadata.to_account_info(),
+                    identity_mint,
+                    keychain.to_account_info(),
+                    ctx.accounts.authority.to_account_info(),
+                    ctx.accounts.system_program.to_account_info(),
+                    ctx.accounts.rent.to_account_info(),
+                    ctx.accounts.token_metadata_program.to_account_info(),
+                ],
+                signer,
+            )?;
         }
 
+        ctx.accounts.keychain_state.identity_holder = Some(authority);
+        ctx.accounts.keychain_state.record_activity()?;
+        log_activity(&ctx.accounts.keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::MintIdentity,
+                     authority, ctx.accounts.identity_mint.key())?;
+        msg!("minted identity for keychain {} to {}", keychain.key(), authority);
         Ok(())
     }
+
+    // checks + uses up a signed intent for another program acting on a keychain's behalf (e.g. yardsale delisting an
+    // item). returns the key that signed it
+    pub fn use_intent(ctx: Context<UseIntent>, intent: Intent) -> Result <Pubkey> {
+        // keychain actions have their own relay_ instructions
+        let program = match intent.action {
+            IntentAction::Delist { program, .. } | IntentAction::UpdatePrice { program, .. } => program,
+            _ => return err!(KeychainError::InvalidIntent),
+        };
+        // only the program the intent's for can use it (otherwise anyone could burn the nonce with a copy)
+        let (program_signer, _) = Pubkey::find_program_address(&[INTENT_AUTHORITY.as_bytes()], &program);
+        require!(ctx.accounts.program_signer.key() == program_signer, KeychainError::InvalidIntent);
+        relay_intent(Some(&ctx.accounts.instructions), &ctx.accounts.keychain, &mut ctx.accounts.keychain_state, &intent)
+    }
 }
 
 
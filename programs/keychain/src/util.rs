@@ -1,17 +1,679 @@
-use anchor_lang::prelude::Pubkey;
-use crate::account::{CurrentKeyChain, KeyChainState};
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{ed25519_program, program::{invoke, invoke_signed}, system_instruction};
+use anchor_lang::solana_program::sysvar::instructions::get_instruction_relative;
+use anchor_spl::token::{self, Burn, Mint, ThawAccount, Token, TokenAccount, Transfer};
+use mpl_token_metadata::state::{Metadata, TokenMetadataAccount};
+use crate::account::*;
+use crate::constant::*;
+use crate::context::*;
+use crate::error::KeychainError;
 
 // checks that a given string contains only lowercase letters and numbers, with a few special characters
 pub fn is_valid_name(s: &str) -> bool {
     s.chars().all(|c| !c.is_whitespace()  && (c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_'))
 }
 
+// display names can be any unicode, just not blank or with control characters (newlines, etc.)
+pub fn check_display_name(display_name: &str) -> Result<()> {
+    require!(display_name.as_bytes().len() <= MAX_DISPLAY_NAME_LENGTH, KeychainError::DisplayNameTooLong);
+    require!(!display_name.trim().is_empty(), KeychainError::InvalidDisplayName);
+    require!(!display_name.chars().any(|c| c.is_control()), KeychainError::InvalidDisplayName);
+    Ok(())
+}
+
+// the key cost held in a key account (anything above rent) while its add is pending
+pub fn held_key_cost(keychain_key: &AccountInfo) -> Result<u64> {
+    let rent = Rent::get()?.minimum_balance(keychain_key.data_len());
+    Ok(keychain_key.lamports().saturating_sub(rent))
+}
+
+// moves the held key cost out of the (program-owned) key account to the domain treasury
+pub fn release_key_cost(keychain_key: &AccountInfo, treasury: &AccountInfo) -> Result<()> {
+    let amount = held_key_cost(keychain_key)?;
+    **keychain_key.try_borrow_mut_lamports()? -= amount;
+    **treasury.try_borrow_mut_lamports()? += amount;
+    Ok(())
+}
+
+// records to the keychain's activity log - a no-op until the keychain has one, after which it's required
+pub fn log_activity(keychain_state: &KeyChainState, activity_log: Option<&mut Account<ActivityLog>>,
+                    activity_type: ActivityType, actor: Pubkey, target: Pubkey) -> Result<()> {
+    if !keychain_state.has_activity_log {
+        return Ok(());
+    }
+    let activity_log = activity_log.ok_or(KeychainError::MissingActivityLog)?;
+    activity_log.record(activity_type, actor, target)
+}
+
+// closes the keychain's activity log (if it has one) when the keychain itself gets closed
+pub fn close_activity_log<'info>(keychain_state: &KeyChainState, activity_log: Option<&mut Account<'info, ActivityLog>>,
+                                 destination: AccountInfo<'info>) -> Result<()> {
+    if !keychain_state.has_activity_log {
+        return Ok(());
+    }
+    let activity_log = activity_log.ok_or(KeychainError::MissingActivityLog)?;
+    activity_log.close(destination)
+}
+
+// sets up a newly created domain + its state account with the default params
+pub fn init_domain(domain: &mut Account<CurrentDomain>, domain_state: &mut Account<DomainState>, name: String,
+                   authority: Pubkey, treasury: Pubkey, key_cost: u64, bump: u8, state_bump: u8) -> Result<()> {
+    domain_state.version = CURRENT_DOMAIN_VERSION;
+    domain_state.domain = domain.key();
+    domain_state.bump = state_bump;
+    domain_state.created_at = Clock::get()?.unix_timestamp;
+
+    domain.name = name;
+    domain.authority = authority;
+    domain.key_cost = key_cost;
+    domain.treasury = treasury;
+    domain.bump = bump;
+    domain.keychain_action_threshold = DEFAULT_DOMAIN_KEYCHAIN_ACTION_THRESHOLD;
+    domain.max_keys = MAX_KEYS as u8;
+    domain.min_name_len = MIN_NAME_LENGTH as u8;
+    domain.max_name_len = MAX_NAME_LENGTH as u8;
+    domain.name_charset = NameCharset::Lowercase;
+    // a single veto cancels
+    domain.rejection_threshold = 0;
+    Ok(())
+}
+
+// grows an account that's smaller than its current layout (e.g. to store a bump), with the payer topping up the rent
+pub fn grow_account<'info>(account: &AccountInfo<'info>,
+                           payer: &AccountInfo<'info>,
+                           system_program: &AccountInfo<'info>,
+                           len: usize) -> Result<()> {
+    if account.data_len() >= len {
+        return Ok(());
+    }
+    let lamports = Rent::get()?.minimum_balance(len).saturating_sub(account.lamports());
+    if lamports > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, account.key, lamports),
+            &[payer.clone(), account.clone(), system_program.clone()],
+        )?;
+    }
+    account.realloc(len, true)?;
+    Ok(())
+}
+
+// sets up a newly created keychain + its state and pointer account, with the wallet as the first key
+pub fn init_keychain(keychain: &mut Account<CurrentKeyChain>,
+                     keychain_state: &mut Account<KeyChainState>,
+                     keychain_key: &mut KeyChainKey,
+                     domain: &CurrentDomain,
+                     name: String,
+                     wallet: Pubkey,
+                     bump: u8,
+                     state_bump: u8,
+                     key_bump: u8) -> Result<()> {
+
+    keychain_state.keychain_version = CURRENT_KEYCHAIN_VERSION;
+    keychain_state.keychain = keychain.key();
+    keychain_state.bump = state_bump;
+    keychain_state.action_threshold = domain.keychain_action_threshold;
+    keychain_state.record_activity()?;
+
+    let key = UserKey::new(wallet);
+
+    keychain.name = name;
+    keychain.num_keys = 1;
+    keychain.domain = domain.name.clone();
+    keychain.bump = bump;
+    keychain.keys = vec![key];
+
+    // now set up the pointer/map account
+    keychain_key.key = wallet;
+    keychain_key.keychain = keychain.key();
+    keychain_key.bump = key_bump;
+    keychain_key.version = CURRENT_KEY_VERSION;
+    Ok(())
+}
+
+// pays for a verified key - straight to the treasury if its add has passed, otherwise it's held in the key account until
+// the add goes through (and refunded if it gets rejected). then sets up the key account, adding the key if the add passed
+pub fn complete_verify<'info>(keychain: &mut Account<'info, CurrentKeyChain>,
+                              keychain_state: &mut Account<'info, KeyChainState>,
+                              keychain_key: &mut Account<'info, KeyChainKey>,
+                              domain_state: &mut DomainState,
+                              key: Pubkey,
+                              payer: &AccountInfo<'info>,
+                              treasury: &AccountInfo<'info>,
+                              system_program: &AccountInfo<'info>,
+                              key_cost: u64,
+                              key_bump: u8) -> Result<()> {
+    // check that the payer can pay for this
+    if payer.lamports() < key_cost {
+        return Err(KeychainError::NotEnoughSol.into());
+    }
+
+    // either we've hit the threshold or all keys have voted
+    let threshold_reached = keychain.threshold_reached(&keychain_state.pending_action.as_ref().unwrap().votes, keychain_state.action_threshold);
+
+    let recipient = if threshold_reached {
+        treasury.clone()
+    } else {
+        keychain_key.to_account_info()
+    };
+    invoke(
+        &system_instruction::transfer(payer.key, recipient.key, key_cost),
+        &[payer.clone(), recipient.clone(), system_program.clone()],
+    )?;
+
+    // set up the pointer/map account
+    keychain_key.key = key;
+    keychain_key.keychain = keychain.key();
+    keychain_key.bump = key_bump;
+    keychain_key.version = CURRENT_KEY_VERSION;
+
+    if threshold_reached {
+        // clear the pending action and add it to the keychain
+        keychain_state.pending_action = None;
+        keychain.add_key(key);
+        domain_state.collect_fee(key_cost);
+        domain_state.add_key();
+    } else {
+        // then we haven't reached the threshold yet - but make sure we've set the verified
+        keychain_state.pending_action.as_mut().unwrap().verified = true;
+    }
+    Ok(())
+}
+
 pub fn set_vote(keychain: &CurrentKeyChain, keychain_state: &mut KeyChainState, signer: &Pubkey, vote: bool) {
     let pending_action = keychain_state.pending_action.as_mut().unwrap();
     let authority_index = keychain.index_of(signer).unwrap() as u8;
+    // a key's vote goes one way or the other
     if vote {
         pending_action.votes.set_index(authority_index);
+        pending_action.rejections.unset_index(authority_index);
     } else {
         pending_action.votes.unset_index(authority_index);
+        pending_action.rejections.set_index(authority_index);
+    }
+}
+
+// moves sol (vault_token = None) or spl tokens out of a keychain's vault - the vault pda signs
+pub fn transfer_from_vault<'info>(keychain: &Pubkey,
+                                  domain: &str,
+                                  vault: &AccountInfo<'info>,
+                                  vault_bump: u8,
+                                  vault_token: Option<&AccountInfo<'info>>,
+                                  destination: &AccountInfo<'info>,
+                                  program: &AccountInfo<'info>,
+                                  amount: u64) -> Result<()> {
+
+    let seeds = &[
+        keychain.as_ref(),
+        VAULT_SPACE.as_bytes().as_ref(),
+        domain.as_bytes().as_ref(),
+        KEYCHAIN.as_bytes().as_ref(),
+        &[vault_bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    if let Some(vault_token) = vault_token {
+        let cpi_accounts = Transfer {
+            from: vault_token.clone(),
+            to: destination.clone(),
+            authority: vault.clone(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(program.clone(), cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+    } else {
+        invoke_signed(
+            &system_instruction::transfer(vault.key, destination.key, amount),
+            &[vault.clone(), destination.clone(), program.clone()],
+            signer,
+        )?;
+    }
+
+    Ok(())
+}
+
+// a keychain can't be closed while its vault holds anything - the vault's address comes from the keychain's, so its funds
+// would be stranded (and inherited by the next keychain created with the same name). token accounts can't be looked up
+// on-chain, so the vault's token accounts get passed in
+pub fn check_vault_empty(vault: &AccountInfo, vault_tokens: &[AccountInfo]) -> Result<()> {
+    require!(vault.lamports() == 0, KeychainError::VaultNotEmpty);
+    for vault_token in vault_tokens {
+        require_keys_eq!(*vault_token.owner, token::ID, KeychainError::InvalidVaultAccount);
+        let vault_token = TokenAccount::try_deserialize(&mut &vault_token.try_borrow_data()?[..])?;
+        require_keys_eq!(vault_token.owner, vault.key(), KeychainError::InvalidVaultAccount);
+        require!(vault_token.amount == 0, KeychainError::VaultNotEmpty);
+    }
+    Ok(())
+}
+
+// invokes a proposal's instruction, signed by either the keychain pda or the vault pda (vault = Some(vault, bump))
+pub fn execute_proposal<'info>(proposal: &KeyChainProposal,
+                               keychain: &Account<'info, CurrentKeyChain>,
+                               vault: Option<(&AccountInfo<'info>, u8)>,
+                               remaining_accounts: &[AccountInfo<'info>]) -> Result<()> {
+
+    let mut account_infos = remaining_accounts.to_vec();
+    account_infos.push(keychain.to_account_info());
+
+    let keychain_key = keychain.key();
+    let name_seed = CurrentKeyChain::name_seed(&keychain.name);
+    let vault_bump;
+    let keychain_bump = [keychain.bump];
+    let seeds: Vec<&[u8]> = if let Some((vault, bump)) = vault {
+        account_infos.push(vault.clone());
+        vault_bump = [bump];
+        vec![
+            keychain_key.as_ref(),
+            VAULT_SPACE.as_bytes().as_ref(),
+            keychain.domain.as_bytes().as_ref(),
+            KEYCHAIN.as_bytes().as_ref(),
+            &vault_bump,
+        ]
+    } else {
+        vec![
+            name_seed.as_ref(),
+            KEYCHAIN_SPACE.as_bytes().as_ref(),
+            keychain.domain.as_bytes().as_ref(),
+            KEYCHAIN.as_bytes().as_ref(),
+            &keychain_bump,
+        ]
+    };
+
+    invoke_signed(&proposal.instruction(), &account_infos, &[&seeds[..]])?;
+
+    Ok(())
+}
+
+// burns the keychain's identity nft if the given key (leaving the keychain) holds it. the keychain pda is the freeze
+// authority and the token account's delegate, so it can thaw + burn without the holder
+pub fn release_identity<'info>(keychain: &Account<'info, CurrentKeyChain>,
+                               keychain_state: &mut KeyChainState,
+                               key: &Pubkey,
+                               identity_mint: Option<&Account<'info, Mint>>,
+                               identity_token: Option<&Account<'info, TokenAccount>>,
+                               token_program: Option<&Program<'info, Token>>) -> Result<()> {
+    if keychain_state.identity_holder != Some(*key) {
+        return Ok(());
+    }
+    require!(identity_mint.is_some() && identity_token.is_some() && token_program.is_some(), KeychainError::MissingIdentityAccount);
+    let identity_mint = identity_mint.unwrap();
+    let identity_token = identity_token.unwrap();
+    require!(identity_token.mint == identity_mint.key() && identity_token.owner == *key, KeychainError::InvalidIdentityAccount);
+
+    burn_identity(keychain, &identity_mint.to_account_info(), identity_token, &token_program.unwrap().to_account_info())?;
+    keychain_state.identity_holder = None;
+    Ok(())
+}
+
+pub fn burn_identity<'info>(keychain: &Account<'info, CurrentKeyChain>,
+                            identity_mint: &AccountInfo<'info>,
+                            identity_token: &Account<'info, TokenAccount>,
+                            token_program: &AccountInfo<'info>) -> Result<()> {
+    let name_seed = CurrentKeyChain::name_seed(&keychain.name);
+    let seeds = &[
+        name_seed.as_ref(),
+        KEYCHAIN_SPACE.as_bytes().as_ref(),
+        keychain.domain.as_bytes().as_ref(),
+        KEYCHAIN.as_bytes().as_ref(),
+        &[keychain.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    if identity_token.is_frozen() {
+        token::thaw_account(CpiContext::new_with_signer(token_program.clone(), ThawAccount {
+            account: identity_token.to_account_info(),
+            mint: identity_mint.clone(),
+            authority: keychain.to_account_info(),
+        }, signer))?;
+    }
+    token::burn(CpiContext::new_with_signer(token_program.clone(), Burn {
+        mint: identity_mint.clone(),
+        from: identity_token.to_account_info(),
+        authority: keychain.to_account_info(),
+    }, signer), identity_token.amount)?;
+
+    Ok(())
+}
+
+// proposes adding a key (signer = the key on the keychain proposing it, either the tx signer or from a signed intent)
+pub fn process_add_key(ctx: Context<AddKey>, signer: Pubkey, key: Pubkey) -> Result<()> {
+    let keychain = &mut ctx.accounts.keychain;
+
+    require!(keychain.has_key(&signer), KeychainError::NotAuthorized);
+
+    require!(!keychain.has_key(&key), KeychainError::KeyAlreadyExists);
+    require!(usize::from(keychain.num_keys) < ctx.accounts.domain.key_limit(), KeychainError::MaxKeys);
+
+    // check that there isn't already a pending action
+    let keychain_state = &mut ctx.accounts.keychain_state;
+    require!(keychain_state.pending_action.is_none(), KeychainError::PendingActionExists);
+
+    // signer automatically casts vote to approve
+    let mut pending_action = PendingKeyChainAction::new(KeyChainActionType::AddKey, key);
+    let authority_index = keychain.index_of(&signer).unwrap() as u8;
+    pending_action.votes.set_index(authority_index);
+
+    keychain_state.pending_action = Some(pending_action);
+    keychain_state.record_activity()?;
+    log_activity(keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::AddKey, signer, key)?;
+
+    // don't even bother checking the threshold cause let's not ever allow just 1 vote to add a key
+
+    // todo: MIGHT wanna add the key account as an optional to mae sure it doesn't exist yet: https://solana.stackexchange.com/questions/3745/anchors-init-if-constraint-for-the-optional-initialization-of-accounts
+
+    Ok(())
+}
+
+// vote = true means confirm & vote = false means reject
+pub fn process_vote<'info>(ctx: Context<'_, '_, '_, 'info, VotePendingAction<'info>>, signer: Pubkey, vote: bool) -> Result<()> {
+    let keychain = &mut ctx.accounts.keychain;
+    require!(keychain.has_key(&signer), KeychainError::NotAuthorized);
+
+    // the key being removed has no vote (or veto) on its own removal
+    if let Some(pending_action) = &ctx.accounts.keychain_state.pending_action {
+        if pending_action.action_type == KeyChainActionType::RemoveKey && pending_action.key == signer {
+            msg!("ignoring vote from the key being removed: {}", signer);
+            return Ok(());
+        }
+    }
+    ctx.accounts.keychain_state.record_activity()?;
+    let activity_type = if vote { ActivityType::Approve } else { ActivityType::Reject };
+    let target = ctx.accounts.keychain_state.pending_action.as_ref().unwrap().key;
+    log_activity(&ctx.accounts.keychain_state, ctx.accounts.activity_log.as_deref_mut(), activity_type, signer, target)?;
+
+    if !vote {
+        // the domain decides whether a single rejection (veto) cancels the pending action or a rejection threshold is needed
+        require!(ctx.accounts.domain.is_some(), KeychainError::MissingDomainAccount);
+        let rejection_threshold = ctx.accounts.domain.as_ref().unwrap().rejection_threshold;
+        set_vote(keychain, &mut ctx.accounts.keychain_state, &signer, vote);
+
+        let rejections = &ctx.accounts.keychain_state.pending_action.as_ref().unwrap().rejections;
+        if keychain.rejection_reached(rejections, rejection_threshold) {
+            // a rejected proposal gets closed too, otherwise it'd block the next one
+            if ctx.accounts.keychain_state.has_pending_action_type(KeyChainActionType::Execute) {
+                require!(ctx.accounts.proposal.is_some(), KeychainError::MissingProposalAccount);
+                let proposal = ctx.accounts.proposal.as_mut().unwrap();
+                let proposer = ctx.accounts.proposer.as_ref();
+                require!(proposer.is_some() && proposer.unwrap().key() == proposal.proposer, KeychainError::InvalidDestination);
+                proposal.close(proposer.unwrap().to_account_info())?;
+            }
+            // a verified key already has its key account - close it so the wallet can be linked again, refunding the
+            // rent + held key cost to the wallet
+            let pending_action = ctx.accounts.keychain_state.pending_action.as_ref().unwrap();
+            if pending_action.action_type == KeyChainActionType::AddKey && pending_action.verified {
+                require!(ctx.accounts.keychain_key.is_some(), KeychainError::MissingKeyAccount);
+                let destination = ctx.accounts.destination.as_ref();
+                require!(destination.is_some() && destination.unwrap().key() == pending_action.key, KeychainError::InvalidDestination);
+                let keychain_key = ctx.accounts.keychain_key.as_mut().unwrap();
+                keychain_key.close(destination.unwrap().to_account_info())?;
+            }
+            // clear the pending action
+            ctx.accounts.keychain_state.pending_action = None;
+        }
+    } else {
+        set_vote(keychain, &mut ctx.accounts.keychain_state, &signer, vote);
+
+        let action_threshold = ctx.accounts.keychain_state.action_threshold;
+        let pending_action = ctx.accounts.keychain_state.pending_action.as_mut().unwrap();
+
+        let threshold_reached = if pending_action.action_type == KeyChainActionType::RemoveKey {
+            keychain.removal_threshold_reached(&pending_action.votes, action_threshold, &pending_action.key)
+        } else {
+            keychain.threshold_reached(&pending_action.votes, action_threshold)
+        };
+
+        if threshold_reached {
+
+            // perform the pending action
+            match pending_action.action_type {
+                KeyChainActionType::AddKey => {
+                    // this would require us to verify first
+                    // require!(pending_action.verified, KeychainError::KeyNotVerified);
+
+                    // if the key has already been verified, then we add it to the keychain and clear the pending action
+                    if pending_action.verified {
+                        require!(ctx.accounts.domain_state.is_some(), KeychainError::MissingDomainAccount);
+                        let domain_state = ctx.accounts.domain_state.as_mut().unwrap();
+
+                        // pay out the key cost held in the key account
+                        require!(ctx.accounts.keychain_key.is_some(), KeychainError::MissingKeyAccount);
+                        let keychain_key = ctx.accounts.keychain_key.as_ref().unwrap().to_account_info();
+                        let key_cost = held_key_cost(&keychain_key)?;
+                        if key_cost > 0 {
+                            require!(ctx.accounts.domain.is_some() && ctx.accounts.treasury.is_some(), KeychainError::InvalidTreasury);
+                            let treasury = ctx.accounts.treasury.as_ref().unwrap();
+                            require!(treasury.key() == ctx.accounts.domain.as_ref().unwrap().treasury, KeychainError::InvalidTreasury);
+                            release_key_cost(&keychain_key, treasury)?;
+                            domain_state.collect_fee(key_cost);
+                        }
+                        keychain.add_key(pending_action.key);
+                        domain_state.add_key();
+                        ctx.accounts.keychain_state.pending_action = None;
+                    }
+                },
+                KeyChainActionType::RemoveKey => {
+                    // remove the key - in this case we need to have been passed in the keychain_key account
+                    require!(ctx.accounts.keychain_key.is_some(), KeychainError::MissingKeyAccount);
+                    require!(ctx.accounts.domain_state.is_some(), KeychainError::MissingDomainAccount);
+                    let removed = pending_action.key;
+                    keychain.remove_key(removed);
+                    let domain_state = ctx.accounts.domain_state.as_mut().unwrap();
+                    domain_state.remove_key();
+                    release_identity(keychain, &mut ctx.accounts.keychain_state, &removed,
+                                     ctx.accounts.identity_mint.as_deref(),
+                                     ctx.accounts.identity_token.as_deref(),
+                                     ctx.accounts.token_program.as_ref())?;
+
+                    // close the keychain_key account - send lamports back to the signer
+                    let refund = refund_account(&ctx.accounts.authority, ctx.accounts.wallet.as_ref(), &signer)?;
+                    let keychain_key = ctx.accounts.keychain_key.as_mut().unwrap();
+                    keychain_key.close(refund)?;
+                    // clear the pending action
+                    ctx.accounts.keychain_state.pending_action = None;
+                },
+                KeyChainActionType::Withdraw { mint, amount } => {
+                    let destination = pending_action.key;
+                    require!(ctx.accounts.vault.is_some(), KeychainError::MissingVaultAccount);
+                    let vault = ctx.accounts.vault.as_ref().unwrap().to_account_info();
+                    let vault_bump = *ctx.bumps.get("vault").unwrap();
+
+                    if let Some(mint) = mint {
+                        require!(ctx.accounts.vault_token.is_some() && ctx.accounts.token_program.is_some(), KeychainError::MissingVaultAccount);
+                        require!(ctx.accounts.destination_token.is_some(), KeychainError::InvalidDestination);
+                        let vault_token = ctx.accounts.vault_token.as_ref().unwrap();
+                        let destination_token = ctx.accounts.destination_token.as_ref().unwrap();
+                        require!(vault_token.owner == vault.key() && vault_token.mint == mint, KeychainError::InvalidVaultAccount);
+                        require!(destination_token.key() == destination && destination_token.mint == mint, KeychainError::InvalidDestination);
+
+                        transfer_from_vault(&keychain.key(), &keychain.domain, &vault, vault_bump,
+                                            Some(&vault_token.to_account_info()),
+                                            &destination_token.to_account_info(),
+                                            &ctx.accounts.token_program.as_ref().unwrap().to_account_info(),
+                                            amount)?;
+                    } else {
+                        require!(ctx.accounts.system_program.is_some(), KeychainError::MissingVaultAccount);
+                        require!(ctx.accounts.destination.is_some(), KeychainError::InvalidDestination);
+                        let destination_ai = ctx.accounts.destination.as_ref().unwrap();
+                        require!(destination_ai.key() == destination, KeychainError::InvalidDestination);
+
+                        transfer_from_vault(&keychain.key(), &keychain.domain, &vault, vault_bump,
+                                            None,
+                                            destination_ai,
+                                            &ctx.accounts.system_program.as_ref().unwrap().to_account_info(),
+                                            amount)?;
+                    }
+
+                    // clear the pending action
+                    ctx.accounts.keychain_state.pending_action = None;
+                },
+                KeyChainActionType::Execute => {
+                    require!(ctx.accounts.proposal.is_some(), KeychainError::MissingProposalAccount);
+                    let proposal = ctx.accounts.proposal.as_ref().unwrap();
+                    require!(proposal.key() == pending_action.key, KeychainError::InvalidProposal);
+
+                    let vault = if proposal.use_vault {
+                        require!(ctx.accounts.vault.is_some(), KeychainError::MissingVaultAccount);
+                        Some((ctx.accounts.vault.as_ref().unwrap().to_account_info(), *ctx.bumps.get("vault").unwrap()))
+                    } else {
+                        None
+                    };
+
+                    execute_proposal(proposal, keychain, vault.as_ref().map(|(vault, bump)| (vault, *bump)), ctx.remaining_accounts)?;
+
+                    // close the proposal - send lamports back to the proposer
+                    let proposal = ctx.accounts.proposal.as_mut().unwrap();
+                    let proposer = ctx.accounts.proposer.as_ref();
+                    require!(proposer.is_some() && proposer.unwrap().key() == proposal.proposer, KeychainError::InvalidDestination);
+                    proposal.close(proposer.unwrap().to_account_info())?;
+                    // clear the pending action
+                    ctx.accounts.keychain_state.pending_action = None;
+                },
+                KeyChainActionType::SetWeight { weight } => {
+                    if let Some(key) = keychain.get_key(&pending_action.key) {
+                        key.weight = weight;
+                    }
+                    ctx.accounts.keychain_state.pending_action = None;
+                },
+                KeyChainActionType::SetThreshold { threshold } => {
+                    ctx.accounts.keychain_state.action_threshold = threshold;
+                    ctx.accounts.keychain_state.pending_action = None;
+                },
+                KeyChainActionType::Merge { .. } => {
+                    // stays pending until both keychains have approved and merge_keychains is called
+                    pending_action.verify();
+                },
+                KeyChainActionType::Detach { .. } => {
+                    // stays pending until detach_key creates the new keychain
+                    pending_action.verify();
+                },
+                KeyChainActionType::SetBeneficiary { beneficiary, inactivity_period } => {
+                    ctx.accounts.keychain_state.beneficiary = beneficiary;
+                    ctx.accounts.keychain_state.inactivity_period = inactivity_period;
+                    ctx.accounts.keychain_state.pending_action = None;
+                },
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// proposes removing a key, or closes the keychain if it's the only one
+pub fn process_remove_key(ctx: Context<RemoveKey>, signer: Pubkey, key: Pubkey) -> Result<()> {
+    let keychain = &mut ctx.accounts.keychain;
+    let keychain_state = &mut ctx.accounts.keychain_state;
+    require!(keychain.has_key(&signer), KeychainError::NotAuthorized);
+
+    // if this is the only linked key, then we close the whole keychain
+    if keychain.num_keys == 1 {
+        msg!("Closing keychain: {}", keychain.key());
+        require!(ctx.accounts.vault.is_some(), KeychainError::MissingVaultAccount);
+        check_vault_empty(ctx.accounts.vault.as_ref().unwrap(), ctx.remaining_accounts)?;
+        require!(ctx.accounts.domain_state.is_some(), KeychainError::MissingDomainAccount);
+        let domain_state = ctx.accounts.domain_state.as_mut().unwrap();
+        domain_state.remove_keychain();
+        domain_state.remove_key();
+        release_identity(keychain, keychain_state, &key,
+                         ctx.accounts.identity_mint.as_deref(),
+                         ctx.accounts.identity_token.as_deref(),
+                         ctx.accounts.token_program.as_ref())?;
+        // close the keychain (and its log)
+        let refund = refund_account(&ctx.accounts.authority, ctx.accounts.wallet.as_ref(), &signer)?;
+        close_activity_log(keychain_state, ctx.accounts.activity_log.as_deref_mut(), refund.clone())?;
+        keychain.close(refund.clone())?;
+        keychain_state.close(refund.clone())?;
+        let keychain_key = &mut ctx.accounts.keychain_key;
+        keychain_key.close(refund)?;
+
+    } else {
+        // votes
+        let mut pending_action = PendingKeyChainAction::new(KeyChainActionType::RemoveKey, key);
+        // a key removing itself doesn't get a vote (the remaining keys decide)
+        if signer != key {
+            let authority_index = keychain.index_of(&signer).unwrap() as u8;
+            pending_action.vote(authority_index, true);
+        }
+        keychain_state.pending_action = Some(pending_action);
+        keychain_state.record_activity()?;
+        log_activity(keychain_state, ctx.accounts.activity_log.as_deref_mut(), ActivityType::RemoveKey, signer, key)?;
+    }
+
+    Ok(())
+}
+
+// where a closed account's rent goes: the authority, unless it's relaying for another key, in which case the intent
+// signer's wallet gets it (rather than the relayer)
+fn refund_account<'info>(authority: &Signer<'info>, wallet: Option<&SystemAccount<'info>>, signer: &Pubkey) -> Result<AccountInfo<'info>> {
+    if authority.key() == *signer {
+        return Ok(authority.to_account_info());
+    }
+    let wallet = wallet.ok_or(KeychainError::InvalidDestination)?;
+    require!(wallet.key() == *signer, KeychainError::InvalidDestination);
+    Ok(wallet.to_account_info())
+}
+
+// finds the ed25519 signature check right before this instruction and returns the key that signed the intent
+pub fn intent_signer(instructions: &AccountInfo, intent: &Intent) -> Result<Pubkey> {
+    let ix = get_instruction_relative(-1, instructions).map_err(|_| KeychainError::InvalidIntentSignature)?;
+    require!(ix.program_id == ed25519_program::ID, KeychainError::InvalidIntentSignature);
+
+    // a single signature, with the signature, key and message all in the ed25519 instruction itself
+    let data = &ix.data;
+    require!(data.len() >= 16 && data[0] == 1, KeychainError::InvalidIntentSignature);
+    let read_u16 = |offset: usize| usize::from(u16::from_le_bytes([data[offset], data[offset + 1]]));
+    let this_ix = usize::from(u16::MAX);
+    require!(read_u16(4) == this_ix && read_u16(8) == this_ix && read_u16(14) == this_ix, KeychainError::InvalidIntentSignature);
+
+    let (key_offset, message_offset, message_len) = (read_u16(6), read_u16(10), read_u16(12));
+    require!(data.len() >= key_offset + 32 && data.len() >= message_offset + message_len, KeychainError::InvalidIntentSignature);
+
+    let mut message = INTENT_PREFIX.as_bytes().to_vec();
+    message.extend(intent.try_to_vec()?);
+    require!(data[message_offset..message_offset + message_len] == message[..], KeychainError::InvalidIntentSignature);
+
+    Ok(Pubkey::new_from_array(data[key_offset..key_offset + 32].try_into().unwrap()))
+}
+
+// checks a relayed intent was signed by a key on the keychain, then uses up its nonce. returns the signer
+pub fn relay_intent(instructions: Option<&UncheckedAccount>, keychain: &CurrentKeyChain, keychain_state: &mut KeyChainState,
+                    intent: &Intent) -> Result<Pubkey> {
+    let instructions = instructions.ok_or(KeychainError::InvalidIntentSignature)?;
+    let signer = intent_signer(&instructions.to_account_info(), intent)?;
+    require!(keychain.has_key(&signer), KeychainError::NotAuthorized);
+    keychain_state.use_intent(intent, Clock::get()?.unix_timestamp)?;
+    keychain_state.record_activity()?;
+    Ok(signer)
+}
+
+// totals a keychain's holdings of a mint (or of nfts in a verified metaplex collection, when collection = true) across
+// the given token accounts, which need to be owned by keys on the keychain. for collections, each token account is
+// followed by its mint's metadata account. a token account passed more than once only gets counted once
+pub fn keychain_holdings(keychain: &CurrentKeyChain, accounts: &[AccountInfo], mint: &Pubkey, collection: bool) -> Result<u64> {
+    let step = if collection { 2 } else { 1 };
+    require!(accounts.len() % step == 0, KeychainError::InvalidHoldingsAccount);
+
+    let mut counted: Vec<Pubkey> = Vec::new();
+    let mut total: u64 = 0;
+    for chunk in accounts.chunks(step) {
+        let token_account_info = &chunk[0];
+        if counted.contains(token_account_info.key) {
+            continue;
+        }
+        require!(*token_account_info.owner == token::ID, KeychainError::InvalidHoldingsAccount);
+        let token_account = TokenAccount::try_deserialize(&mut &token_account_info.try_borrow_data()?[..])?;
+        require!(keychain.has_key(&token_account.owner), KeychainError::InvalidHoldingsAccount);
+
+        if collection {
+            let metadata_info = &chunk[1];
+            require!(*metadata_info.owner == mpl_token_metadata::ID, KeychainError::InvalidHoldingsAccount);
+            let metadata = Metadata::from_account_info(metadata_info)?;
+            require!(metadata.mint == token_account.mint, KeychainError::InvalidHoldingsAccount);
+            let in_collection = metadata.collection.map_or(false, |c| c.verified && c.key == *mint);
+            require!(in_collection, KeychainError::InvalidHoldingsAccount);
+        } else {
+            require!(token_account.mint == *mint, KeychainError::InvalidHoldingsAccount);
+        }
+
+        counted.push(*token_account_info.key);
+        total = total.saturating_add(token_account.amount);
     }
+    Ok(total)
 }
@@ -0,0 +1,47 @@
+// loads key accounts on older layouts the way instructions do (through Account::try_from)
+
+use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+use keychain::account::{CurrentKeyChainKey, KeyChainKey};
+use keychain::constant::CURRENT_KEY_VERSION;
+use keychain::error::KeychainError;
+
+// hands the data to check as a key account owned by the keychain program
+fn load(data: &mut [u8], check: impl FnOnce(Result<Account<CurrentKeyChainKey>>)) {
+    let (address, mut lamports) = (Pubkey::new_unique(), 1_000_000_000);
+    let info = AccountInfo::new(&address, false, true, &mut lamports, data, &keychain::ID, false, 0);
+    check(Account::try_from(&info));
+}
+
+fn assert_old_version(err: Option<Error>) {
+    assert_eq!(err.expect("an old key account should be rejected"), KeychainError::InvalidKeychainVersion.into());
+}
+
+#[test]
+fn rejects_v0_key_accounts_as_an_old_version() {
+    let (keychain, key) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+    // created on verify before the bump + version were stored: just the keychain + key, with no extra space
+    let mut data = [&KeyChainKey::discriminator()[..], keychain.as_ref(), key.as_ref()].concat();
+    assert_eq!(data.len(), 72);
+    load(&mut data, |account| assert_old_version(account.err()));
+
+    // created with the extra space, so the bump + version read as 0
+    data.resize(8 + KeyChainKey::MAX_SIZE, 0);
+    load(&mut data, |account| {
+        let account = account.unwrap();
+        assert_eq!(account.keychain, keychain);
+        assert_old_version(account.checked_bump().err());
+    });
+}
+
+#[test]
+fn loads_current_key_accounts() {
+    let mut data = Vec::new();
+    KeyChainKey { keychain: Pubkey::new_unique(), key: Pubkey::new_unique(), bump: 254, version: CURRENT_KEY_VERSION }
+        .try_serialize(&mut data).unwrap();
+    load(&mut data, |account| assert_eq!(account.unwrap().checked_bump().unwrap(), 254));
+
+    // another account type that happens to be short still fails the discriminator check
+    load(&mut [0u8; 72], |account| assert_eq!(account.err().unwrap(), ErrorCode::AccountDiscriminatorMismatch.into()));
+}
@@ -1,6 +1,9 @@
 // root seed
 pub const YARDSALE: &str = "yardsale";
 
+// signs for the keychain program's use_intent
+pub use keychain::constant::INTENT_AUTHORITY;
+
 // listings
 pub const LISTINGS: &str = "listings";
 
@@ -38,7 +38,7 @@ pub struct ListItem<'info> {
     #[account(
         init,
         payer = authority,
-        seeds = [item.key().as_ref(), LISTINGS.as_bytes().as_ref(), keychain.name.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), YARDSALE.as_bytes().as_ref()],
+        seeds = [item.key().as_ref(), LISTINGS.as_bytes().as_ref(), CurrentKeyChain::name_seed(&keychain.name).as_ref(), keychain.domain.as_bytes().as_ref(), YARDSALE.as_bytes().as_ref()],
         bump,
         space = 8 + Listing::MAX_SIZE,
     )]
@@ -137,6 +137,100 @@ pub struct UpdatePrice<'info> {
     pub authority: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct RelayDelistItem<'info> {
+
+    #[account(
+        mut,
+        has_one = item,
+        constraint = listing.item == item.key() && listing.item_token == listing_item_token.key() && listing.domain == keychain.domain && listing.keychain == keychain.name,
+        close = seller,
+    )]
+    pub listing: Box<Account<'info, Listing>>,
+
+    #[account(
+        constraint = keychain.has_key(&seller.key()),
+    )]
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    /// CHECK: the intent's nonce gets used up by the keychain program, which checks this
+    #[account(mut)]
+    pub keychain_state: UncheckedAccount<'info>,
+
+    pub item: Box<Account<'info, Mint>>,
+
+    // the token account the item gets returned to
+    #[account(
+        mut,
+        token::mint = item,
+        token::authority = seller
+    )]
+    pub seller_item_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = item,
+        associated_token::authority = listing
+    )]
+    pub listing_item_token: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: doesn't sign - checked against the intent's signer
+    #[account(mut)]
+    pub seller: UncheckedAccount<'info>,
+
+    // submits + pays for the transaction
+    #[account(mut)]
+    pub relayer: Signer<'info>,
+
+    pub keychain_program: Program<'info, Keychain>,
+
+    /// CHECK: just a signer for the keychain program's use_intent
+    #[account(seeds = [INTENT_AUTHORITY.as_bytes()], bump)]
+    pub intent_authority: UncheckedAccount<'info>,
+
+    //sysvar ixs don't deserialize in anchor
+    /// CHECK: address below
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct RelayUpdatePrice<'info> {
+
+    #[account(
+        mut,
+        has_one = item,
+        constraint = listing.item == item.key() && listing.domain == keychain.domain && listing.keychain == keychain.name,
+    )]
+    pub listing: Box<Account<'info, Listing>>,
+
+    // the intent's signer gets checked against the keychain by the keychain program
+    pub keychain: Box<Account<'info, CurrentKeyChain>>,
+
+    /// CHECK: the intent's nonce gets used up by the keychain program, which checks this
+    #[account(mut)]
+    pub keychain_state: UncheckedAccount<'info>,
+
+    pub item: Box<Account<'info, Mint>>,
+
+    // submits + pays for the transaction
+    pub relayer: Signer<'info>,
+
+    pub keychain_program: Program<'info, Keychain>,
+
+    /// CHECK: just a signer for the keychain program's use_intent
+    #[account(seeds = [INTENT_AUTHORITY.as_bytes()], bump)]
+    pub intent_authority: UncheckedAccount<'info>,
+
+    //sysvar ixs don't deserialize in anchor
+    /// CHECK: address below
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
 #[derive(Accounts)]
 pub struct ListPNFT<'info> {
 
@@ -162,7 +256,7 @@ pub struct ListPNFT<'info> {
     #[account(
         init,
         payer = authority,
-        seeds = [item.key().as_ref(), LISTINGS.as_bytes().as_ref(), keychain.name.as_bytes().as_ref(), keychain.domain.as_bytes().as_ref(), YARDSALE.as_bytes().as_ref()],
+        seeds = [item.key().as_ref(), LISTINGS.as_bytes().as_ref(), CurrentKeyChain::name_seed(&keychain.name).as_ref(), keychain.domain.as_bytes().as_ref(), YARDSALE.as_bytes().as_ref()],
         bump,
         space = 8 + Listing::MAX_SIZE,
     )]
@@ -25,5 +25,7 @@ pub enum YardsaleError {
     #[msg("Bad Ruleset")]
     BadRuleset,
     #[msg("TransferBuilder failed")]
-    TransferBuilderFailed
+    TransferBuilderFailed,
+    #[msg("Intent doesn't match this instruction")]
+    InvalidIntent,
 }
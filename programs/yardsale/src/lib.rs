@@ -14,6 +14,8 @@ use mpl_token_metadata::{
     state::{Metadata, ProgrammableConfig::V1, TokenMetadataAccount, TokenStandard},
 };
 
+use keychain::account::{Intent, IntentAction};
+
 // "prod" / staging address
 declare_id!("yar3RNWQaixwFAcAXZ4wySQAiyuSxSQYGCp4AjAotM1");
 
@@ -170,6 +172,44 @@ pub mod yardsale {
         Ok(())
     }
 
+    // delist an item with an intent signed by the seller - a relayer submits (and pays for) the transaction
+    pub fn relay_delist_item(ctx: Context<RelayDelistItem>, intent: Intent) -> Result<()> {
+        require!(intent.action == IntentAction::Delist { program: crate::ID, listing: ctx.accounts.listing.key() }, YardsaleError::InvalidIntent);
+        let signer = use_keychain_intent(ctx.accounts.keychain_program.to_account_info(),
+                                         ctx.accounts.keychain.to_account_info(),
+                                         ctx.accounts.keychain_state.to_account_info(),
+                                         ctx.accounts.instructions.to_account_info(),
+                                         ctx.accounts.intent_authority.to_account_info(),
+                                         *ctx.bumps.get("intent_authority").unwrap(),
+                                         intent)?;
+        require!(signer == ctx.accounts.seller.key(), YardsaleError::InvalidIntent);
+
+        let listing = &ctx.accounts.listing;
+        let listing_item_token_ai = ctx.accounts.listing_item_token.to_account_info();
+        let seller_item_token_ai = ctx.accounts.seller_item_token.to_account_info();
+        let lamports_claimer_ai = ctx.accounts.seller.to_account_info();
+        let token_prog_ai = ctx.accounts.token_program.to_account_info();
+
+        // transfer the item back to the seller
+        transfer_item_and_close(listing, listing_item_token_ai, seller_item_token_ai, lamports_claimer_ai, token_prog_ai)
+    }
+
+    // update the price of an item with an intent signed by a key on the listing's keychain
+    pub fn relay_update_price(ctx: Context<RelayUpdatePrice>, price: u64, intent: Intent) -> Result<()> {
+        require!(intent.action == IntentAction::UpdatePrice { program: crate::ID, listing: ctx.accounts.listing.key(), price }, YardsaleError::InvalidIntent);
+        use_keychain_intent(ctx.accounts.keychain_program.to_account_info(),
+                            ctx.accounts.keychain.to_account_info(),
+                            ctx.accounts.keychain_state.to_account_info(),
+                            ctx.accounts.instructions.to_account_info(),
+                            ctx.accounts.intent_authority.to_account_info(),
+                            *ctx.bumps.get("intent_authority").unwrap(),
+                            intent)?;
+
+        let listing = &mut ctx.accounts.listing;
+        listing.price = price;
+        Ok(())
+    }
+
     // purchase an item
     pub fn purchase_item(ctx: Context<PurchaseItem>) -> Result<()> {
         let listing = &ctx.accounts.listing;
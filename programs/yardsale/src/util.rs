@@ -1,6 +1,7 @@
 use anchor_lang::solana_program::system_instruction;
 use mpl_token_auth_rules::payload::{Payload, PayloadType, SeedsVec};
 use mpl_token_metadata::state::PayloadKey;
+use keychain::account::{CurrentKeyChain, Intent};
 use crate::*;
 
 
@@ -9,23 +10,15 @@ pub fn assert_decode_metadata<'info>(
     nft_mint: &Account<'info, Mint>,
     metadata_account: &AccountInfo<'info>,
 ) -> Result<Metadata> {
-    let (key, _) = Pubkey::find_program_address(
-        &[
-            mpl_token_metadata::state::PREFIX.as_bytes(),
-            mpl_token_metadata::id().as_ref(),
-            nft_mint.key().as_ref(),
-        ],
-        &mpl_token_metadata::id(),
-    );
-    if key != *metadata_account.key {
-        require!(true, YardsaleError::BadMetadata);
-    }
-    // Check account owner (redundant because of find_program_address above, but why not).
+    // a metadata account owned by the metadata program that points at this mint is the mint's metadata - no need to
+    // re-derive its address
     if *metadata_account.owner != mpl_token_metadata::id() {
         return Err(error!(YardsaleError::BadMetadata));
     }
+    let metadata = Metadata::from_account_info(metadata_account)?;
+    require_keys_eq!(metadata.mint, nft_mint.key(), YardsaleError::BadMetadata);
 
-    Ok(Metadata::from_account_info(metadata_account)?)
+    Ok(metadata)
 }
 
 
@@ -37,10 +30,11 @@ pub fn transfer_item_and_close<'a, 'b>(listing: &Box<Account<'a, Listing>>,
                                    token_program: AccountInfo<'a>) -> Result<()>
     where 'a: 'b, 'b: 'a {
 
+    let keychain_seed = CurrentKeyChain::name_seed(&listing.keychain);
     let seeds = &[
         listing.item.as_ref(),
         LISTINGS.as_bytes().as_ref(),
-        listing.keychain.as_bytes().as_ref(),
+        keychain_seed.as_ref(),
         listing.domain.as_bytes().as_ref(),
         YARDSALE.as_bytes().as_ref(),
         &[listing.bump],
@@ -242,10 +236,11 @@ pub fn send_pnft<'info>(
         .instruction();
 
     if let Some(listing) = program_signer {
+        let keychain_seed = CurrentKeyChain::name_seed(&listing.keychain);
         let signer_seeds = &[
             listing.item.as_ref(),
             LISTINGS.as_bytes().as_ref(),
-            listing.keychain.as_bytes().as_ref(),
+            keychain_seed.as_ref(),
             listing.domain.as_bytes().as_ref(),
             YARDSALE.as_bytes().as_ref(),
             &[listing.bump],
@@ -283,7 +278,7 @@ pub fn tranfer_pnft_from_pda<'info>(
     let seeds = SeedsVec {
         seeds: vec![listing.item.key().as_ref().to_vec(),
                     LISTINGS.as_bytes().to_vec(),
-                    listing.keychain.as_bytes().to_vec(),
+                    CurrentKeyChain::name_seed(&listing.keychain),
                     listing.domain.as_bytes().to_vec(),
                     YARDSALE.as_bytes().to_vec(),
         ]
@@ -366,10 +361,11 @@ pub fn tranfer_pnft_from_pda<'info>(
 
     msg!("invoking transfer instruction");
 
+    let keychain_seed = CurrentKeyChain::name_seed(&listing.keychain);
     let seeds = &[
         listing.item.as_ref(),
         LISTINGS.as_bytes().as_ref(),
-        listing.keychain.as_bytes().as_ref(),
+        keychain_seed.as_ref(),
         listing.domain.as_bytes().as_ref(),
         YARDSALE.as_bytes().as_ref(),
         &[listing.bump],
@@ -395,10 +391,11 @@ pub fn close_listing_owned_account<'info>(
         destination: lamports_collector,
         authority: listing.to_account_info(),
     };
+    let keychain_seed = CurrentKeyChain::name_seed(&listing.keychain);
     let signer_seeds = &[
         listing.item.as_ref(),
         LISTINGS.as_bytes().as_ref(),
-        listing.keychain.as_bytes().as_ref(),
+        keychain_seed.as_ref(),
         listing.domain.as_bytes().as_ref(),
         YARDSALE.as_bytes().as_ref(),
         &[listing.bump],
@@ -410,3 +407,18 @@ pub fn close_listing_owned_account<'info>(
 
     Ok(())
 }
+
+// has the keychain program check + use up an intent signed by a key on the keychain, returning the key that signed it.
+// signed with our intent authority pda so the keychain program knows the intent's being used by us
+pub fn use_keychain_intent<'info>(keychain_program: AccountInfo<'info>,
+                                  keychain: AccountInfo<'info>,
+                                  keychain_state: AccountInfo<'info>,
+                                  instructions: AccountInfo<'info>,
+                                  intent_authority: AccountInfo<'info>,
+                                  intent_authority_bump: u8,
+                                  intent: Intent) -> Result<Pubkey> {
+    let cpi_accounts = keychain::cpi::accounts::UseIntent { keychain, keychain_state, instructions, program_signer: intent_authority };
+    let signer_seeds: &[&[u8]] = &[INTENT_AUTHORITY.as_bytes(), &[intent_authority_bump]];
+    let signer = &[signer_seeds];
+    Ok(keychain::cpi::use_intent(CpiContext::new_with_signer(keychain_program, cpi_accounts, signer), intent)?.get())
+}
@@ -4,14 +4,32 @@ import { Keychain } from "../target/types/keychain";
 import { Profile } from "../target/types/profile";
 import * as assert from "assert";
 import {Keypair, LAMPORTS_PER_SOL, PublicKey, sendAndConfirmTransaction, Transaction} from "@solana/web3.js";
-import {createAssociatedTokenAccount, createMint, mintToChecked} from "@solana/spl-token";
+import {
+  ASSOCIATED_TOKEN_PROGRAM_ID, createAssociatedTokenAccount, createMint, createThawAccountInstruction, getAccount,
+  getAssociatedTokenAddressSync, getMint, mintToChecked, TOKEN_PROGRAM_ID
+} from "@solana/spl-token";
+import {PROGRAM_ID as TOKEN_METADATA_PROGRAM_ID} from "@metaplex-foundation/mpl-token-metadata";
 import {
   createNFTMint,
   findDomainPda,
+  findBlockedKeyPda,
+  getCreateDomainAccounts,
+  initializeKeychain,
+  findProtocolConfigPda,
+  findDomainApprovalPda,
+  findProgramDataAddress,
+  listDomains,
+  GLOBAL_DOMAIN,
   findDomainStatePda,
   findKeychainKeyPda,
   findKeychainPda,
-  findKeychainStatePda, findProfilePda
+  findKeychainProposalPda,
+  findKeychainStatePda, findKeychainVaultPda, findProfilePda, findWalletKeychainPda,
+  createIntentSignatureIx,
+  findIdentityMintPda,
+  findMetadataPda,
+  findActivityLogPda,
+  logComputeUnits,
 } from "./utils";
 import {expect} from "chai";
 const { SystemProgram } = anchor.web3;
@@ -79,6 +97,8 @@ describe("keychain", () => {
     const [key3KeyPda, key3KeyPdaBump] = findKeychainKeyPda(key3.publicKey, domain, keychainProgram.programId);
     const [key4KeyPda, key4KeyPdaBump] = findKeychainKeyPda(key4.publicKey, domain, keychainProgram.programId);
     const [adminPlayerKeychainPda, adminPlayerKeychainPdaBump] = findKeychainPda(adminPlayername, domain, keychainProgram.programId);
+    const [playerVaultPda, playerVaultPdaBump] = findKeychainVaultPda(playerKeychainPda, domain, keychainProgram.programId);
+    const [playerProposalPda, playerProposalPdaBump] = findKeychainProposalPda(playerKeychainPda, domain, keychainProgram.programId);
 
     console.log(`domain: ${domain}`);
     console.log(`domain pda: ${domainPda.toBase58()}`);
@@ -164,9 +184,12 @@ describe("keychain", () => {
 
       let txid;
 
+      await initializeKeychain(keychainProgram, provider, treasury.publicKey, renameCost);
+
       txid = await keychainProgram.methods.createDomain(domain, renameCost).accounts({
             domain: domainPda,
             domainState: domainStatePda,
+            ...(await getCreateDomainAccounts(keychainProgram)),
             authority: provider.wallet.publicKey,
             systemProgram: SystemProgram.programId,
             treasury: treasury.publicKey
@@ -188,6 +211,12 @@ describe("keychain", () => {
         console.log('domain state: ', domainStatePda.toBase58());
         console.log('-- version: ', domainStateAcct.version);
         console.log('-- domain: ', domainStateAcct.domain.toBase58());
+        assert.equal(domainStateAcct.bump, findDomainStatePda(domain, keychainProgram.programId)[1]);
+
+        // the new domain + the global domain should both be discoverable through the registry
+        const domains = await listDomains(keychainProgram);
+        assert.ok(domains.some(d => d.address.equals(domainPda) && d.domain.keyCost.eq(renameCost)), 'domain should be in the registry');
+        assert.ok(domains.some(d => d.domain.name == GLOBAL_DOMAIN), 'global domain should be in the registry');
 
         console.log(`player keychain key pda: ${playerKeychainKeyPda.toBase58()}`);
 
@@ -198,12 +227,20 @@ describe("keychain", () => {
             keychainState: playerKeychainStatePda,
             keychainKey: playerKeychainKeyPda,
             domain: domainPda,
+            domainState: domainStatePda,
             authority: randomPlayerKeypair.publicKey,
             wallet: randomPlayerKeypair.publicKey,
             systemProgram: SystemProgram.programId,
+            blockedKey: findBlockedKeyPda(randomPlayerKeypair.publicKey, domain, keychainProgram.programId)[0],
         }).rpc();
 
       console.log(`created 1st keychain tx: ${txid}`);
+      await logComputeUnits(provider.connection, txid, 'create keychain');
+
+      domainStateAcct = await keychainProgram.account.domainState.fetch(domainStatePda);
+      assert.equal(domainStateAcct.numKeychains.toNumber(), 1);
+      assert.equal(domainStateAcct.numKeys.toNumber(), 1);
+      assert.ok(domainStateAcct.createdAt.toNumber() > 0, 'domain creation time should be set');
 
       keychain = await keychainProgram.account.currentKeyChain.fetch(playerKeychainPda);
       let keychainState = await keychainProgram.account.keyChainState.fetch(playerKeychainStatePda);
@@ -219,6 +256,24 @@ describe("keychain", () => {
         console.log('\nkeychain key: ', keychainKey);
         console.log('-- keychain: ', keychainKey.keychain.toBase58());
         console.log('-- key: ', keychainKey.key.toBase58());
+        // bumps are stored so later instructions don't have to re-derive them
+        assert.equal(keychainKey.bump, findKeychainKeyPda(randomPlayerKeypair.publicKey, domain, keychainProgram.programId)[1]);
+        assert.equal(keychainState.bump, findKeychainStatePda(playerKeychainPda, domain, keychainProgram.programId)[1]);
+        assert.equal(keychainKey.version, 1);
+
+        // already on the current version, so there's nothing to migrate
+        try {
+          await keychainProgram.methods.migrateKey().accounts({
+            domain: domainPda,
+            wallet: randomPlayerKeypair.publicKey,
+            keychainKey: playerKeychainKeyPda,
+            payer: provider.wallet.publicKey,
+            systemProgram: SystemProgram.programId,
+          }).rpc();
+          assert.fail("shouldn't be able to migrate a current key account");
+        } catch (err) {
+          expect(err.toString()).to.include('InvalidKeychainVersion');
+        }
 
       // try to create another from same playername/app
       try {
@@ -228,9 +283,11 @@ describe("keychain", () => {
                   keychainState: playerKeychainStatePda,
                   keychainKey: playerKeychainKeyPda,
                   domain: domainPda,
+                  domainState: domainStatePda,
                   authority: provider.wallet.publicKey,
                   wallet: provider.wallet.publicKey,
                   systemProgram: SystemProgram.programId,
+                  blockedKey: findBlockedKeyPda(provider.wallet.publicKey, domain, keychainProgram.programId)[0],
               }
           });
           assert.fail("shouldn't be able to create same keychain again");
@@ -274,7 +331,7 @@ describe("keychain", () => {
       // now try upgrading it
 
       txid = await keychainProgram.methods.upgradeKeychain().accounts({
-        authority: provider.wallet.publicKey,
+        payer: provider.wallet.publicKey,
         keychain: oldkeychainPda,
         keychainState: oldkeychainStatePda,
         systemProgram: SystemProgram.programId,
@@ -292,6 +349,18 @@ describe("keychain", () => {
       console.log('-- num keys: ', keychain.numKeys);
       console.log('-- keys: ', keychain.keys);
       console.log('-- key 1: ', keychain.keys[0].key.toBase58());
+
+      // the key account is on the old layout too
+      txid = await keychainProgram.methods.migrateKey().accounts({
+        domain: domainPda,
+        wallet: oldkey.publicKey,
+        keychainKey: oldkeychainKey,
+        payer: provider.wallet.publicKey,
+        systemProgram: SystemProgram.programId,
+      }).rpc();
+      let key = await keychainProgram.account.keyChainKey.fetch(oldkeychainKey);
+      assert.equal(key.version, 1);
+      assert.equal(key.bump, oldkeychainKeyBump);
     });
      */
 
@@ -304,6 +373,8 @@ describe("keychain", () => {
               keychain: playerKeychainPda,
               keychainState: playerKeychainStatePda,
               authority: randomPlayerKeypair.publicKey,
+              domain: domainPda,
+              blockedKey: findBlockedKeyPda(key2.publicKey, domain, keychainProgram.programId)[0],
           }
       });
       console.log(`added key ${key2.publicKey.toBase58()} to keychain: ${txid}`);
@@ -324,7 +395,9 @@ describe("keychain", () => {
         await randomPlayerProgram.methods.addKey(key2.publicKey).accounts({
           keychain: playerKeychainPda,
           keychainState: playerKeychainStatePda,
+          domain: domainPda,
           authority: randomPlayerKeypair.publicKey,
+          blockedKey: findBlockedKeyPda(key2.publicKey, domain, keychainProgram.programId)[0],
         }).rpc();
         assert.fail("shouldn't be able to add same key again");
       } catch (err) {
@@ -350,12 +423,14 @@ describe("keychain", () => {
 
       txid = await keychainProgram.methods.verifyKey().accounts({
           domain: domainPda,
+          domainState: domainStatePda,
           keychain: playerKeychainPda,
           keychainState: playerKeychainStatePda,
           keychainKey: key2KeyPda,
           authority: key2.publicKey,
           treasury: treasury.publicKey,
-          systemProgram: SystemProgram.programId
+          systemProgram: SystemProgram.programId,
+          blockedKey: findBlockedKeyPda(key2.publicKey, domain, keychainProgram.programId)[0],
       }).signers([key2]).rpc();
 
       let key = await keychainProgram.account.keyChainKey.fetch(key2KeyPda);
@@ -396,19 +471,23 @@ describe("keychain", () => {
         let txid = await randomPlayerProgram.methods.addKey(key3.publicKey).accounts({
             keychain: playerKeychainPda,
             keychainState: playerKeychainStatePda,
+            domain: domainPda,
             authority: key2.publicKey,
+            blockedKey: findBlockedKeyPda(key3.publicKey, domain, keychainProgram.programId)[0],
         }).signers([key2]).rpc();
         console.log(`added key ${key3.publicKey.toBase58()} to keychain: ${txid}`);
 
         // now the key3 account needs to verify
         txid = await keychainProgram.methods.verifyKey().accounts({
           domain: domainPda,
+          domainState: domainStatePda,
           keychain: playerKeychainPda,
           keychainState: playerKeychainStatePda,
           keychainKey: key3KeyPda,
           authority: key3.publicKey,
           treasury: treasury.publicKey,
-          systemProgram: SystemProgram.programId
+          systemProgram: SystemProgram.programId,
+          blockedKey: findBlockedKeyPda(key3.publicKey, domain, keychainProgram.programId)[0],
         }).signers([key3]).rpc();
 
         // check the votes. since key2 voted, value should be 2
@@ -438,16 +517,31 @@ describe("keychain", () => {
         keychainState = await keychainProgram.account.keyChainState.fetch(playerKeychainStatePda);
         expect(keychainState.pendingAction).to.exist;
 
+        // the key cost is held in the key account until the add goes through
+        assert.equal(await provider.connection.getBalance(treasury.publicKey), treasuryBalance, 'key cost should be held until the add executes');
+
+        let domainStateBefore = await keychainProgram.account.domainState.fetch(domainStatePda);
+
         // so now we vote w/1st key - which should execute the add
-        await randomPlayerProgram.methods.votePendingAction(true).accounts({
+        txid = await randomPlayerProgram.methods.votePendingAction(true).accounts({
           keychain: playerKeychainPda,
           keychainState: playerKeychainStatePda,
-          keychainKey: null,
+          keychainKey: key3KeyPda,
+          domainState: domainStatePda,
           authority: randomPlayerKeypair.publicKey,
+          domain: domainPda,
+          treasury: treasury.publicKey,
         }).rpc();
+        await logComputeUnits(provider.connection, txid, 'vote pending action (add key)');
 
         keychainState = await keychainProgram.account.keyChainState.fetch(playerKeychainStatePda);
         expect(keychainState.pendingAction).to.be.null;
+        assert.equal(await provider.connection.getBalance(treasury.publicKey), treasuryBalance + renameCost.toNumber(), 'key cost should be paid out to the treasury');
+
+        // the domain's stats pick up the key + its fee
+        let domainStateAfter = await keychainProgram.account.domainState.fetch(domainStatePda);
+        assert.equal(domainStateAfter.numKeys.toNumber(), domainStateBefore.numKeys.toNumber() + 1);
+        assert.equal(domainStateAfter.totalFeesCollected.toNumber(), domainStateBefore.totalFeesCollected.toNumber() + renameCost.toNumber());
 
         // now there should be 3 keys
         let keychain = await keychainProgram.account.currentKeyChain.fetch(playerKeychainPda);
@@ -480,12 +574,14 @@ describe("keychain", () => {
       let keyAccount = await keychainProgram.account.keyChainKey.fetch(playerKeychainKeyPda);
 
       // so now we vote w/2nd key - which should execute the removal
-      await randomPlayerProgram.methods.votePendingAction(true).accounts({
+      const txid = await randomPlayerProgram.methods.votePendingAction(true).accounts({
         keychain: playerKeychainPda,
         keychainState: playerKeychainStatePda,
         keychainKey: playerKeychainKeyPda,
+        domainState: domainStatePda,
         authority: key2.publicKey,
       }).signers([key2]).rpc();
+      await logComputeUnits(provider.connection, txid, 'vote pending action (remove key)');
 
       let keychainState = await keychainProgram.account.keyChainState.fetch(playerKeychainStatePda);
       expect(keychainState.pendingAction).to.be.null;
@@ -510,7 +606,9 @@ describe("keychain", () => {
         let txid = await randomPlayerProgram.methods.addKey(key5.publicKey).accounts({
           keychain: playerKeychainPda,
           keychainState: playerKeychainStatePda,
+          domain: domainPda,
           authority: key2.publicKey,
+          blockedKey: findBlockedKeyPda(key5.publicKey, domain, keychainProgram.programId)[0],
         }).signers([key2]).rpc();
 
         // now we approve w/key3 - so all approvals are in, but not verified yet
@@ -521,10 +619,22 @@ describe("keychain", () => {
           authority: key3.publicKey,
         }).signers([key3]).rpc();
 
+        // key3 takes its approval back without vetoing
+        let votesBefore = (await keychainProgram.account.keyChainState.fetch(playerKeychainStatePda)).pendingAction.votes.data;
+        await randomPlayerProgram.methods.retractVote().accounts({
+          keychain: playerKeychainPda,
+          keychainState: playerKeychainStatePda,
+          authority: key3.publicKey,
+        }).signers([key3]).rpc();
+        let pendingAction = (await keychainProgram.account.keyChainState.fetch(playerKeychainStatePda)).pendingAction;
+        expect(pendingAction).to.exist;
+        assert.ok(pendingAction.votes.data < votesBefore, "key3's vote should be cleared");
+
         // now we cancel with key2
         await randomPlayerProgram.methods.votePendingAction(false).accounts({
           keychain: playerKeychainPda,
           keychainState: playerKeychainStatePda,
+          domain: domainPda,
           keychainKey: null,
           authority: key2.publicKey,
         }).signers([key2]).rpc();
@@ -538,6 +648,64 @@ describe("keychain", () => {
         assert.ok(keychain.keys.length == 2, 'canceled add, so should still be 2 keys');
       });
 
+      it("rejects a verified add and refunds the key account", async () => {
+        const key6 = anchor.web3.Keypair.generate();
+        const [key6KeyPda] = findKeychainKeyPda(key6.publicKey, domain, keychainProgram.programId);
+        await provider.connection.confirmTransaction(
+            await provider.connection.requestAirdrop(key6.publicKey, anchor.web3.LAMPORTS_PER_SOL * 0.1),
+            "confirmed"
+        );
+
+        await randomPlayerProgram.methods.addKey(key6.publicKey).accounts({
+          keychain: playerKeychainPda,
+          keychainState: playerKeychainStatePda,
+          domain: domainPda,
+          authority: key2.publicKey,
+          blockedKey: findBlockedKeyPda(key6.publicKey, domain, keychainProgram.programId)[0],
+        }).signers([key2]).rpc();
+
+        // verifying creates the key account (holding the key cost)
+        await keychainProgram.methods.verifyKey().accounts({
+          domain: domainPda,
+          domainState: domainStatePda,
+          keychain: playerKeychainPda,
+          keychainState: playerKeychainStatePda,
+          keychainKey: key6KeyPda,
+          authority: key6.publicKey,
+          treasury: treasury.publicKey,
+          systemProgram: SystemProgram.programId,
+          blockedKey: findBlockedKeyPda(key6.publicKey, domain, keychainProgram.programId)[0],
+        }).signers([key6]).rpc();
+        const key6Balance = await provider.connection.getBalance(key6.publicKey);
+        const keyAccountBalance = await provider.connection.getBalance(key6KeyPda);
+
+        // key3 rejects - the key account gets closed + refunded to key6
+        await randomPlayerProgram.methods.votePendingAction(false).accounts({
+          keychain: playerKeychainPda,
+          keychainState: playerKeychainStatePda,
+          domain: domainPda,
+          keychainKey: key6KeyPda,
+          authority: key3.publicKey,
+          destination: key6.publicKey,
+        }).signers([key3]).rpc();
+
+        expect(await keychainProgram.account.keyChainKey.fetchNullable(key6KeyPda)).to.be.null;
+        assert.equal(await provider.connection.getBalance(key6.publicKey), key6Balance + keyAccountBalance, 'rent + key cost should be refunded');
+
+        // a key that's actually on its keychain isn't orphaned
+        try {
+          await keychainProgram.methods.closeOrphanedKey().accounts({
+            keychainKey: key2KeyPda,
+            keychain: playerKeychainPda,
+            keychainState: playerKeychainStatePda,
+            wallet: key2.publicKey,
+          }).rpc();
+          assert.fail("shouldn't be able to close a key account that's in use");
+        } catch (err) {
+          expect(err.toString()).to.include('KeyNotOrphaned');
+        }
+      });
+
       it("Adds ANOTHER key to the keychain, this time approves it THEN verifies", async () => {
         let treasuryBalance = await provider.connection.getBalance(treasury.publicKey);
         console.log("treasury balance before adding 3rd key: ", treasuryBalance);
@@ -546,7 +714,9 @@ describe("keychain", () => {
         let txid = await randomPlayerProgram.methods.addKey(key4.publicKey).accounts({
           keychain: playerKeychainPda,
           keychainState: playerKeychainStatePda,
+          domain: domainPda,
           authority: key2.publicKey,
+          blockedKey: findBlockedKeyPda(key4.publicKey, domain, keychainProgram.programId)[0],
         }).signers([key2]).rpc();
 
         console.log(`added key ${key4.publicKey.toBase58()} to keychain: ${txid}`);
@@ -570,12 +740,14 @@ describe("keychain", () => {
         // now the key4 needs to verify
         txid = await keychainProgram.methods.verifyKey().accounts({
           domain: domainPda,
+          domainState: domainStatePda,
           keychain: playerKeychainPda,
           keychainState: playerKeychainStatePda,
           keychainKey: key4KeyPda,
           authority: key4.publicKey,
           treasury: treasury.publicKey,
-          systemProgram: SystemProgram.programId
+          systemProgram: SystemProgram.programId,
+          blockedKey: findBlockedKeyPda(key4.publicKey, domain, keychainProgram.programId)[0],
         }).signers([key4]).rpc();
 
         console.log(`verified key ${key4.publicKey.toBase58()} with key ${key4.publicKey.toBase58()}`);
@@ -610,6 +782,7 @@ describe("keychain", () => {
         await randomPlayerProgram.methods.votePendingAction(false).accounts({
           keychain: playerKeychainPda,
           keychainState: playerKeychainStatePda,
+          domain: domainPda,
           keychainKey: key4KeyPda,
           authority: key3.publicKey,
         }).signers([key3]).rpc();
@@ -644,11 +817,24 @@ describe("keychain", () => {
         // keyaccount still exists
         let keyAccount = await keychainProgram.account.keyChainKey.fetch(key4KeyPda);
 
+        // the 4th key has no say in its own removal, so its veto is ignored
+        await randomPlayerProgram.methods.votePendingAction(false).accounts({
+          keychain: playerKeychainPda,
+          keychainState: playerKeychainStatePda,
+          domain: domainPda,
+          keychainKey: key4KeyPda,
+          authority: key4.publicKey,
+        }).signers([key4]).rpc();
+
+        let pendingState = await keychainProgram.account.keyChainState.fetch(playerKeychainStatePda);
+        expect(pendingState.pendingAction).to.not.be.null;
+
         // so now we vote w/3rd key - which should execute the removal
         await randomPlayerProgram.methods.votePendingAction(true).accounts({
           keychain: playerKeychainPda,
           keychainState: playerKeychainStatePda,
           keychainKey: key4KeyPda,
+          domainState: domainStatePda,
           authority: key3.publicKey,
         }).signers([key3]).rpc();
 
@@ -669,6 +855,157 @@ describe("keychain", () => {
       });
 
 
+      it("deposits into the keychain vault and withdraws with a vote", async () => {
+        const depositAmount = new anchor.BN(anchor.web3.LAMPORTS_PER_SOL * 0.1);
+        const withdrawAmount = new anchor.BN(anchor.web3.LAMPORTS_PER_SOL * 0.05);
+
+        // key2 deposits sol into the vault
+        await randomPlayerProgram.methods.deposit(depositAmount).accounts({
+          keychain: playerKeychainPda,
+          vault: playerVaultPda,
+          authorityToken: null,
+          vaultToken: null,
+          authority: key2.publicKey,
+          tokenProgram: null,
+          systemProgram: SystemProgram.programId,
+        }).signers([key2]).rpc();
+
+        let vaultBalance = await provider.connection.getBalance(playerVaultPda);
+        assert.ok(vaultBalance == depositAmount.toNumber(), 'vault should hold the deposit');
+
+        // key2 proposes sending some of it to key3
+        await randomPlayerProgram.methods.withdraw(key3.publicKey, null, withdrawAmount).accounts({
+          keychain: playerKeychainPda,
+          keychainState: playerKeychainStatePda,
+          authority: key2.publicKey,
+        }).signers([key2]).rpc();
+
+        // nothing moves until the threshold is reached
+        vaultBalance = await provider.connection.getBalance(playerVaultPda);
+        assert.ok(vaultBalance == depositAmount.toNumber(), 'withdrawal shouldn\'t execute before threshold');
+
+        const key3Balance = await provider.connection.getBalance(key3.publicKey);
+
+        // key3 approves, which executes the withdrawal (key2 pays the fee)
+        await randomPlayerProgram.methods.votePendingAction(true).accounts({
+          keychain: playerKeychainPda,
+          keychainState: playerKeychainStatePda,
+          keychainKey: null,
+          authority: key3.publicKey,
+          vault: playerVaultPda,
+          vaultToken: null,
+          destination: key3.publicKey,
+          destinationToken: null,
+          tokenProgram: null,
+          systemProgram: SystemProgram.programId,
+        }).signers([key3]).rpc();
+
+        let keychainState = await keychainProgram.account.keyChainState.fetch(playerKeychainStatePda);
+        expect(keychainState.pendingAction).to.be.null;
+
+        vaultBalance = await provider.connection.getBalance(playerVaultPda);
+        assert.ok(vaultBalance == depositAmount.sub(withdrawAmount).toNumber(), 'vault balance should be reduced');
+      });
+
+      it("executes a proposed instruction signed by the vault", async () => {
+        const amount = anchor.web3.LAMPORTS_PER_SOL * 0.01;
+        const vaultBalance = await provider.connection.getBalance(playerVaultPda);
+
+        // propose a plain system transfer out of the vault
+        const ix = SystemProgram.transfer({fromPubkey: playerVaultPda, toPubkey: key2.publicKey, lamports: amount});
+        const metas = ix.keys.map((meta) => ({pubkey: meta.pubkey, isSigner: meta.isSigner, isWritable: meta.isWritable}));
+
+        // a proposal can't ask for anyone else's signature (it'd be the final voter's)
+        const voterIx = SystemProgram.transfer({fromPubkey: key3.publicKey, toPubkey: key2.publicKey, lamports: amount});
+        try {
+          await randomPlayerProgram.methods.proposeInstruction(voterIx.programId, voterIx.keys.map((meta) => ({
+            pubkey: meta.pubkey, isSigner: meta.isSigner, isWritable: meta.isWritable,
+          })), voterIx.data, true).accounts({
+            keychain: playerKeychainPda,
+            keychainState: playerKeychainStatePda,
+            proposal: playerProposalPda,
+            authority: key2.publicKey,
+            systemProgram: SystemProgram.programId,
+          }).signers([key2]).rpc();
+          assert.fail("shouldn't be able to propose an instruction signed by the voter");
+        } catch (err) {
+          expect(err.toString()).to.include('InvalidProposal');
+        }
+
+        await randomPlayerProgram.methods.proposeInstruction(ix.programId, metas, ix.data, true).accounts({
+          keychain: playerKeychainPda,
+          keychainState: playerKeychainStatePda,
+          proposal: playerProposalPda,
+          authority: key2.publicKey,
+          systemProgram: SystemProgram.programId,
+        }).signers([key2]).rpc();
+
+        let proposal = await keychainProgram.account.keyChainProposal.fetch(playerProposalPda);
+        assert.ok(proposal.programId.equals(SystemProgram.programId));
+        assert.ok(proposal.proposer.equals(key2.publicKey));
+
+        // key3 approves, which invokes the transfer - the proposal's rent goes back to key2 (the proposer), not key3
+        const approve = (proposer) => randomPlayerProgram.methods.votePendingAction(true).accounts({
+          keychain: playerKeychainPda,
+          keychainState: playerKeychainStatePda,
+          keychainKey: null,
+          authority: key3.publicKey,
+          vault: playerVaultPda,
+          proposal: playerProposalPda,
+          proposer,
+        }).remainingAccounts([
+          {pubkey: key2.publicKey, isSigner: false, isWritable: true},
+          {pubkey: SystemProgram.programId, isSigner: false, isWritable: false},
+        ]).signers([key3]).rpc();
+        try {
+          await approve(key3.publicKey);
+          assert.fail("the voter shouldn't get the proposal's rent");
+        } catch (err) {
+          expect(err.toString()).to.include('InvalidDestination');
+        }
+        const proposalRent = await provider.connection.getBalance(playerProposalPda);
+        const proposerBalance = await provider.connection.getBalance(key2.publicKey);
+        await approve(key2.publicKey);
+        assert.equal(await provider.connection.getBalance(key2.publicKey), proposerBalance + amount + proposalRent);
+
+        let keychainState = await keychainProgram.account.keyChainState.fetch(playerKeychainStatePda);
+        expect(keychainState.pendingAction).to.be.null;
+
+        // proposal gets closed after executing
+        let closedProposal = await keychainProgram.account.keyChainProposal.fetchNullable(playerProposalPda);
+        expect(closedProposal).to.be.null;
+
+        const newVaultBalance = await provider.connection.getBalance(playerVaultPda);
+        assert.ok(newVaultBalance == vaultBalance - amount, 'vault should have sent the transfer');
+      });
+
+      it("changes a key's vote weight with a vote", async () => {
+        // key2 proposes doubling its own weight
+        await randomPlayerProgram.methods.setKeyWeight(key2.publicKey, 2).accounts({
+          keychain: playerKeychainPda,
+          keychainState: playerKeychainStatePda,
+          authority: key2.publicKey,
+        }).signers([key2]).rpc();
+
+        // weight doesn't change until approved
+        let keychain = await keychainProgram.account.currentKeyChain.fetch(playerKeychainPda);
+        assert.ok(keychain.keys.every((k) => k.weight == 1), 'all keys should start with weight 1');
+
+        await randomPlayerProgram.methods.votePendingAction(true).accounts({
+          keychain: playerKeychainPda,
+          keychainState: playerKeychainStatePda,
+          keychainKey: null,
+          authority: key3.publicKey,
+        }).signers([key3]).rpc();
+
+        let keychainState = await keychainProgram.account.keyChainState.fetch(playerKeychainStatePda);
+        expect(keychainState.pendingAction).to.be.null;
+
+        keychain = await keychainProgram.account.currentKeyChain.fetch(playerKeychainPda);
+        const key2Entry = keychain.keys.find((k) => k.key.equals(key2.publicKey));
+        assert.ok(key2Entry.weight == 2, 'key2 should now have a weight of 2');
+      });
+
      it("closes an empty keychain account", async () => {
       let treasuryBalance = await provider.connection.getBalance(treasury.publicKey);
       console.log("treasury balance before removing key (and deleting keychain): ", treasuryBalance);
@@ -689,18 +1026,55 @@ describe("keychain", () => {
         keychain: playerKeychainPda,
         keychainState: playerKeychainStatePda,
         keychainKey: key2KeyPda,
+        domainState: domainStatePda,
         authority: key3.publicKey,
       }).signers([key3]).rpc();
 
+      // the keychain can't be closed while its vault still holds sol
+      try {
+        await keychainProgram.methods.removeKey(key3.publicKey).accounts({
+          keychain: playerKeychainPda,
+          keychainState: playerKeychainStatePda,
+          keychainKey: key3KeyPda,
+          domainState: domainStatePda,
+          authority: key3.publicKey,
+          vault: playerVaultPda,
+        }).signers([key3]).rpc();
+        assert.fail("shouldn't be able to close a keychain with a funded vault");
+      } catch (err) {
+        expect(err.toString()).to.include('VaultNotEmpty');
+      }
+
+      // so key3 empties it first (as the only key, its vote executes the withdrawal)
+      const vaultBalance = await provider.connection.getBalance(playerVaultPda);
+      await keychainProgram.methods.withdraw(key3.publicKey, null, new anchor.BN(vaultBalance)).accounts({
+        keychain: playerKeychainPda,
+        keychainState: playerKeychainStatePda,
+        authority: key3.publicKey,
+      }).signers([key3]).rpc();
+      await keychainProgram.methods.votePendingAction(true).accounts({
+        keychain: playerKeychainPda,
+        keychainState: playerKeychainStatePda,
+        keychainKey: null,
+        authority: key3.publicKey,
+        vault: playerVaultPda,
+        destination: key3.publicKey,
+        systemProgram: SystemProgram.programId,
+      }).signers([key3]).rpc();
+      assert.equal(await provider.connection.getBalance(playerVaultPda), 0);
+
       // now key3 can remove itself (and thus close the entire keychain)
       txid = await keychainProgram.methods.removeKey(key3.publicKey).accounts({
         keychain: playerKeychainPda,
         keychainState: playerKeychainStatePda,
         keychainKey: key3KeyPda,
+        domainState: domainStatePda,
         authority: key3.publicKey,
+        vault: playerVaultPda,
       }).signers([key3]).rpc();
 
       console.log(`removed key and closed keychain account: ${txid}`);
+      await logComputeUnits(provider.connection, txid, 'remove key');
 
       treasuryBalance = await provider.connection.getBalance(treasury.publicKey);
       console.log("treasury balance after removing keychain: ", treasuryBalance);
@@ -718,6 +1092,960 @@ describe("keychain", () => {
   });
 
 
+    it("enforces the domain's name rules", async () => {
+        const updateDomainConfig = (maxKeys: number, minNameLen: number, maxNameLen: number, nameCharset) =>
+            keychainProgram.methods.updateDomainConfig(maxKeys, minNameLen, maxNameLen, nameCharset, 0).accounts({
+                domain: domainPda,
+                authority: provider.wallet.publicKey,
+            }).rpc();
+
+        await updateDomainConfig(5, 6, 32, {lowercase: {}});
+        let domainAcct = await keychainProgram.account.currentDomain.fetch(domainPda);
+        assert.equal(domainAcct.minNameLen, 6);
+
+        const wallet = anchor.web3.Keypair.generate();
+        await provider.connection.confirmTransaction(
+            await provider.connection.requestAirdrop(wallet.publicKey, anchor.web3.LAMPORTS_PER_SOL * 0.1),
+            "confirmed"
+        );
+        const name = 'short';
+        const [keychainPda] = findKeychainPda(name, domain, keychainProgram.programId);
+        const [keychainStatePda] = findKeychainStatePda(keychainPda, domain, keychainProgram.programId);
+        const [keychainKeyPda] = findKeychainKeyPda(wallet.publicKey, domain, keychainProgram.programId);
+
+        try {
+            await keychainProgram.methods.createKeychain(name).accounts({
+                keychain: keychainPda,
+                keychainState: keychainStatePda,
+                keychainKey: keychainKeyPda,
+                domain: domainPda,
+                domainState: domainStatePda,
+                authority: wallet.publicKey,
+                wallet: wallet.publicKey,
+                systemProgram: SystemProgram.programId,
+                blockedKey: findBlockedKeyPda(wallet.publicKey, domain, keychainProgram.programId)[0],
+            }).signers([wallet]).rpc();
+            assert.fail("name should be too short for the domain");
+        } catch (err) {
+            expect(err.toString()).to.include('NameTooShort');
+        }
+
+        // back to the defaults
+        await updateDomainConfig(5, 3, 32, {lowercase: {}});
+    });
+
+    it("creates a wallet keychain", async () => {
+        const wallet = anchor.web3.Keypair.generate();
+        await provider.connection.confirmTransaction(
+            await provider.connection.requestAirdrop(wallet.publicKey, anchor.web3.LAMPORTS_PER_SOL * 0.1),
+            "confirmed"
+        );
+
+        const [walletKeychainPda] = findWalletKeychainPda(wallet.publicKey, domain, keychainProgram.programId);
+        const [walletKeychainStatePda] = findKeychainStatePda(walletKeychainPda, domain, keychainProgram.programId);
+        const [walletKeyPda] = findKeychainKeyPda(wallet.publicKey, domain, keychainProgram.programId);
+
+        await keychainProgram.methods.createWalletKeychain().accounts({
+            keychain: walletKeychainPda,
+            keychainState: walletKeychainStatePda,
+            keychainKey: walletKeyPda,
+            domain: domainPda,
+            domainState: domainStatePda,
+            authority: wallet.publicKey,
+            systemProgram: SystemProgram.programId,
+            blockedKey: findBlockedKeyPda(wallet.publicKey, domain, keychainProgram.programId)[0],
+        }).signers([wallet]).rpc();
+
+        // the wallet's address stands in for the name
+        const keychain = await keychainProgram.account.currentKeyChain.fetch(walletKeychainPda);
+        assert.ok(keychain.name == wallet.publicKey.toBase58(), 'wallet keychain should be named after the wallet');
+        assert.ok(keychain.keys.length == 1 && keychain.keys[0].key.equals(wallet.publicKey));
+
+        const keychainKey = await keychainProgram.account.keyChainKey.fetch(walletKeyPda);
+        assert.ok(keychainKey.keychain.equals(walletKeychainPda));
+
+        // display names aren't seeds, so unicode + mixed case is fine
+        const displayName = 'Jugador Número 1 🎮';
+        await keychainProgram.methods.updateDisplayName(displayName).accounts({
+            keychain: walletKeychainPda,
+            keychainState: walletKeychainStatePda,
+            authority: wallet.publicKey,
+        }).signers([wallet]).rpc();
+
+        const keychainState = await keychainProgram.account.keyChainState.fetch(walletKeychainStatePda);
+        assert.equal(keychainState.displayName, displayName);
+
+        try {
+            await keychainProgram.methods.updateDisplayName('bad\nname').accounts({
+                keychain: walletKeychainPda,
+                keychainState: walletKeychainStatePda,
+                authority: wallet.publicKey,
+            }).signers([wallet]).rpc();
+            assert.fail("control characters shouldn't be allowed");
+        } catch (err) {
+            expect(err.toString()).to.include('InvalidDisplayName');
+        }
+    });
+
+    const mergeSource = anchor.web3.Keypair.generate();
+    const mergeTarget = anchor.web3.Keypair.generate();
+
+    it("merges two keychains", async () => {
+        // two wallet keychains, one key each
+        const [source, target] = [mergeSource, mergeTarget].map((wallet) => {
+            const [keychain] = findWalletKeychainPda(wallet.publicKey, domain, keychainProgram.programId);
+            const [keychainState] = findKeychainStatePda(keychain, domain, keychainProgram.programId);
+            const [keychainKey] = findKeychainKeyPda(wallet.publicKey, domain, keychainProgram.programId);
+            return {wallet, keychain, keychainState, keychainKey};
+        });
+
+        for (const {wallet, keychain, keychainState, keychainKey} of [source, target]) {
+            await provider.connection.confirmTransaction(
+                await provider.connection.requestAirdrop(wallet.publicKey, anchor.web3.LAMPORTS_PER_SOL * 0.1),
+                "confirmed"
+            );
+            await keychainProgram.methods.createWalletKeychain().accounts({
+                keychain, keychainState, keychainKey,
+                domain: domainPda,
+                domainState: domainStatePda,
+                authority: wallet.publicKey,
+                systemProgram: SystemProgram.programId,
+                blockedKey: findBlockedKeyPda(wallet.publicKey, domain, keychainProgram.programId)[0],
+            }).signers([wallet]).rpc();
+        }
+
+        // both sides propose - with a single key, the proposer's vote approves it
+        for (const [from, other, into] of [[source, target, true], [target, source, false]] as const) {
+            await keychainProgram.methods.proposeMerge(into).accounts({
+                keychain: from.keychain,
+                keychainState: from.keychainState,
+                otherKeychain: other.keychain,
+                domain: domainPda,
+                authority: from.wallet.publicKey,
+            }).signers([from.wallet]).rpc();
+        }
+
+        await keychainProgram.methods.mergeKeychains().accounts({
+            source: source.keychain,
+            sourceState: source.keychainState,
+            target: target.keychain,
+            targetState: target.keychainState,
+            domain: domainPda,
+            domainState: domainStatePda,
+            authority: source.wallet.publicKey,
+            sourceVault: findKeychainVaultPda(source.keychain, domain, keychainProgram.programId)[0],
+        }).remainingAccounts([
+            {pubkey: source.keychainKey, isSigner: false, isWritable: true},
+        ]).signers([source.wallet]).rpc();
+
+        const keychain = await keychainProgram.account.currentKeyChain.fetch(target.keychain);
+        assert.equal(keychain.keys.length, 2);
+        assert.ok(keychain.keys.some((k) => k.key.equals(source.wallet.publicKey)), 'source key should be on the target');
+
+        const keychainKey = await keychainProgram.account.keyChainKey.fetch(source.keychainKey);
+        assert.ok(keychainKey.keychain.equals(target.keychain), 'source key should point to the target');
+
+        expect(await keychainProgram.account.currentKeyChain.fetchNullable(source.keychain)).to.be.null;
+        expect(await keychainProgram.account.keyChainState.fetchNullable(source.keychainState)).to.be.null;
+    });
+
+    it("detaches a key into its own keychain", async () => {
+        // undo the merge: the source wallet gets its own (named) keychain again
+        const [targetWallet, sourceWallet] = [mergeTarget, mergeSource];
+        const [targetKeychainPda] = findWalletKeychainPda(targetWallet.publicKey, domain, keychainProgram.programId);
+        const [targetKeychainStatePda] = findKeychainStatePda(targetKeychainPda, domain, keychainProgram.programId);
+        const [sourceKeyPda] = findKeychainKeyPda(sourceWallet.publicKey, domain, keychainProgram.programId);
+
+        const name = 'detached';
+        const [newKeychainPda] = findKeychainPda(name, domain, keychainProgram.programId);
+        const [newKeychainStatePda] = findKeychainStatePda(newKeychainPda, domain, keychainProgram.programId);
+
+        await keychainProgram.methods.proposeDetach(sourceWallet.publicKey, name).accounts({
+            keychain: targetKeychainPda,
+            keychainState: targetKeychainStatePda,
+            domain: domainPda,
+            authority: targetWallet.publicKey,
+        }).signers([targetWallet]).rpc();
+
+        // the threshold is 2, so the other key needs to approve
+        await keychainProgram.methods.votePendingAction(true).accounts({
+            keychain: targetKeychainPda,
+            keychainState: targetKeychainStatePda,
+            keychainKey: null,
+            authority: sourceWallet.publicKey,
+        }).signers([sourceWallet]).rpc();
+
+        const txid = await keychainProgram.methods.detachKey(name).accounts({
+            keychain: targetKeychainPda,
+            keychainState: targetKeychainStatePda,
+            keychainKey: sourceKeyPda,
+            newKeychain: newKeychainPda,
+            newKeychainState: newKeychainStatePda,
+            domain: domainPda,
+            domainState: domainStatePda,
+            authority: sourceWallet.publicKey,
+            systemProgram: SystemProgram.programId,
+        }).signers([sourceWallet]).rpc();
+        await logComputeUnits(provider.connection, txid, 'detach key');
+
+        const oldKeychain = await keychainProgram.account.currentKeyChain.fetch(targetKeychainPda);
+        assert.equal(oldKeychain.keys.length, 1);
+
+        const newKeychain = await keychainProgram.account.currentKeyChain.fetch(newKeychainPda);
+        assert.equal(newKeychain.name, name);
+        assert.ok(newKeychain.keys.length == 1 && newKeychain.keys[0].key.equals(sourceWallet.publicKey));
+
+        const keychainKey = await keychainProgram.account.keyChainKey.fetch(sourceKeyPda);
+        assert.ok(keychainKey.keychain.equals(newKeychainPda), 'key should point to the new keychain');
+    });
+
+    it("blocks a wallet and force removes it from its keychain", async () => {
+        const wallet = mergeSource;
+        const [blockedKeyPda] = findBlockedKeyPda(wallet.publicKey, domain, keychainProgram.programId);
+        const [keychainPda] = findKeychainPda('detached', domain, keychainProgram.programId);
+        const [keychainStatePda] = findKeychainStatePda(keychainPda, domain, keychainProgram.programId);
+        const [keychainKeyPda] = findKeychainKeyPda(wallet.publicKey, domain, keychainProgram.programId);
+
+        await keychainProgram.methods.blockKey(wallet.publicKey).accounts({
+            domain: domainPda,
+            blockedKey: blockedKeyPda,
+            authority: provider.wallet.publicKey,
+            systemProgram: SystemProgram.programId,
+        }).rpc();
+
+        // the domain authority evicts it without a vote - it's the only key, so the keychain gets closed
+        const walletBalance = await provider.connection.getBalance(wallet.publicKey);
+        const txid = await keychainProgram.methods.forceRemoveKey(wallet.publicKey).accounts({
+            domain: domainPda,
+            domainState: domainStatePda,
+            blockedKey: blockedKeyPda,
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: keychainKeyPda,
+            authority: provider.wallet.publicKey,
+            wallet: wallet.publicKey,
+            vault: findKeychainVaultPda(keychainPda, domain, keychainProgram.programId)[0],
+        }).rpc();
+        await logComputeUnits(provider.connection, txid, 'force remove key');
+
+        expect(await keychainProgram.account.keyChainKey.fetchNullable(keychainKeyPda)).to.be.null;
+        expect(await keychainProgram.account.currentKeyChain.fetchNullable(keychainPda)).to.be.null;
+        // the rent goes back to the evicted wallet, which paid for the accounts
+        assert.ok(await provider.connection.getBalance(wallet.publicKey) > walletBalance, 'wallet should get the rent back');
+
+        // and it can't be linked again
+        const [walletKeychainPda] = findWalletKeychainPda(wallet.publicKey, domain, keychainProgram.programId);
+        const [walletKeychainStatePda] = findKeychainStatePda(walletKeychainPda, domain, keychainProgram.programId);
+        try {
+            await keychainProgram.methods.createWalletKeychain().accounts({
+                keychain: walletKeychainPda,
+                keychainState: walletKeychainStatePda,
+                keychainKey: keychainKeyPda,
+                domain: domainPda,
+                domainState: domainStatePda,
+                blockedKey: blockedKeyPda,
+                authority: wallet.publicKey,
+                systemProgram: SystemProgram.programId,
+            }).signers([wallet]).rpc();
+            assert.fail("blocked wallet shouldn't be able to create a keychain");
+        } catch (err) {
+            expect(err.toString()).to.include('KeyBlocked');
+        }
+
+        await keychainProgram.methods.unblockKey().accounts({
+            domain: domainPda,
+            blockedKey: blockedKeyPda,
+            authority: provider.wallet.publicKey,
+        }).rpc();
+        expect(await keychainProgram.account.blockedKey.fetchNullable(blockedKeyPda)).to.be.null;
+    });
+
+    it("needs a new approval for a detach after a force removal", async () => {
+        const [owner, key2, key3] = [0, 1, 2].map(() => anchor.web3.Keypair.generate());
+        const [keychainPda] = findWalletKeychainPda(owner.publicKey, domain, keychainProgram.programId);
+        const [keychainStatePda] = findKeychainStatePda(keychainPda, domain, keychainProgram.programId);
+        for (const wallet of [owner, key2, key3]) {
+            await provider.connection.confirmTransaction(
+                await provider.connection.requestAirdrop(wallet.publicKey, anchor.web3.LAMPORTS_PER_SOL * 0.1),
+                "confirmed"
+            );
+        }
+        await keychainProgram.methods.createWalletKeychain().accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: findKeychainKeyPda(owner.publicKey, domain, keychainProgram.programId)[0],
+            domain: domainPda,
+            domainState: domainStatePda,
+            authority: owner.publicKey,
+            systemProgram: SystemProgram.programId,
+            blockedKey: findBlockedKeyPda(owner.publicKey, domain, keychainProgram.programId)[0],
+        }).signers([owner]).rpc();
+
+        // add key2 (the owner's vote is enough with one key), then key3 (which needs key2's vote too)
+        for (const [key, voter] of [[key2, null], [key3, key2]]) {
+            await keychainProgram.methods.addKey(key.publicKey).accounts({
+                keychain: keychainPda,
+                keychainState: keychainStatePda,
+                domain: domainPda,
+                authority: owner.publicKey,
+                blockedKey: findBlockedKeyPda(key.publicKey, domain, keychainProgram.programId)[0],
+            }).signers([owner]).rpc();
+            if (voter) {
+                await keychainProgram.methods.votePendingAction(true).accounts({
+                    keychain: keychainPda,
+                    keychainState: keychainStatePda,
+                    keychainKey: null,
+                    authority: voter.publicKey,
+                }).signers([voter]).rpc();
+            }
+            await keychainProgram.methods.verifyKey().accounts({
+                domain: domainPda,
+                domainState: domainStatePda,
+                keychain: keychainPda,
+                keychainState: keychainStatePda,
+                keychainKey: findKeychainKeyPda(key.publicKey, domain, keychainProgram.programId)[0],
+                authority: key.publicKey,
+                treasury: treasury.publicKey,
+                systemProgram: SystemProgram.programId,
+                blockedKey: findBlockedKeyPda(key.publicKey, domain, keychainProgram.programId)[0],
+            }).signers([key]).rpc();
+        }
+
+        // approve detaching key3
+        const name = 'redetached';
+        const [newKeychainPda] = findKeychainPda(name, domain, keychainProgram.programId);
+        await keychainProgram.methods.proposeDetach(key3.publicKey, name).accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            domain: domainPda,
+            authority: owner.publicKey,
+        }).signers([owner]).rpc();
+        await keychainProgram.methods.votePendingAction(true).accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: null,
+            authority: key2.publicKey,
+        }).signers([key2]).rpc();
+        let keychainState = await keychainProgram.account.keyChainState.fetch(keychainStatePda);
+        assert.ok(keychainState.pendingAction.verified, 'detach should be approved');
+
+        // the domain evicts key2, whose vote approved it - so the approval no longer stands
+        const [blockedKeyPda] = findBlockedKeyPda(key2.publicKey, domain, keychainProgram.programId);
+        await keychainProgram.methods.blockKey(key2.publicKey).accounts({
+            domain: domainPda,
+            blockedKey: blockedKeyPda,
+            authority: provider.wallet.publicKey,
+            systemProgram: SystemProgram.programId,
+        }).rpc();
+        await keychainProgram.methods.forceRemoveKey(key2.publicKey).accounts({
+            domain: domainPda,
+            domainState: domainStatePda,
+            blockedKey: blockedKeyPda,
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: findKeychainKeyPda(key2.publicKey, domain, keychainProgram.programId)[0],
+            authority: provider.wallet.publicKey,
+            wallet: key2.publicKey,
+        }).rpc();
+        keychainState = await keychainProgram.account.keyChainState.fetch(keychainStatePda);
+        assert.ok(!keychainState.pendingAction.verified, 'detach should need approving again');
+
+        try {
+            await keychainProgram.methods.detachKey(name).accounts({
+                keychain: keychainPda,
+                keychainState: keychainStatePda,
+                keychainKey: findKeychainKeyPda(key3.publicKey, domain, keychainProgram.programId)[0],
+                newKeychain: newKeychainPda,
+                newKeychainState: findKeychainStatePda(newKeychainPda, domain, keychainProgram.programId)[0],
+                domain: domainPda,
+                domainState: domainStatePda,
+                authority: key3.publicKey,
+                systemProgram: SystemProgram.programId,
+            }).signers([key3]).rpc();
+            assert.fail("detach shouldn't go through without a new approval");
+        } catch (err) {
+            expect(err.toString()).to.include('NoPendingAction');
+        }
+
+        await keychainProgram.methods.unblockKey().accounts({
+            domain: domainPda,
+            blockedKey: blockedKeyPda,
+            authority: provider.wallet.publicKey,
+        }).rpc();
+    });
+
+    it("lets a beneficiary claim an inactive keychain", async () => {
+        const owner = anchor.web3.Keypair.generate();
+        const beneficiary = anchor.web3.Keypair.generate();
+        const [keychainPda] = findWalletKeychainPda(owner.publicKey, domain, keychainProgram.programId);
+        const [keychainStatePda] = findKeychainStatePda(keychainPda, domain, keychainProgram.programId);
+        const [ownerKeyPda] = findKeychainKeyPda(owner.publicKey, domain, keychainProgram.programId);
+        const [beneficiaryKeyPda] = findKeychainKeyPda(beneficiary.publicKey, domain, keychainProgram.programId);
+
+        await provider.connection.confirmTransaction(
+            await provider.connection.requestAirdrop(owner.publicKey, anchor.web3.LAMPORTS_PER_SOL * 0.1),
+            "confirmed"
+        );
+        await keychainProgram.methods.createWalletKeychain().accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: ownerKeyPda,
+            domain: domainPda,
+            domainState: domainStatePda,
+            authority: owner.publicKey,
+            systemProgram: SystemProgram.programId,
+            blockedKey: findBlockedKeyPda(owner.publicKey, domain, keychainProgram.programId)[0],
+        }).signers([owner]).rpc();
+
+        // propose + approve a 1 second inactivity period
+        await keychainProgram.methods.setBeneficiary(beneficiary.publicKey, new anchor.BN(1)).accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            authority: owner.publicKey,
+        }).signers([owner]).rpc();
+        await keychainProgram.methods.votePendingAction(true).accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: null,
+            authority: owner.publicKey,
+        }).signers([owner]).rpc();
+
+        let keychainState = await keychainProgram.account.keyChainState.fetch(keychainStatePda);
+        assert.ok(keychainState.beneficiary.equals(beneficiary.publicKey));
+
+        await keychainProgram.methods.heartbeat().accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            authority: owner.publicKey,
+        }).signers([owner]).rpc();
+
+        await new Promise((resolve) => setTimeout(resolve, 2000));
+
+        // anyone can trigger the claim (the provider wallet pays)
+        await keychainProgram.methods.claimKeychain().accounts({
+            domain: domainPda,
+            domainState: domainStatePda,
+            blockedKey: findBlockedKeyPda(beneficiary.publicKey, domain, keychainProgram.programId)[0],
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: beneficiaryKeyPda,
+            beneficiary: beneficiary.publicKey,
+            authority: provider.wallet.publicKey,
+            treasury: treasury.publicKey,
+            systemProgram: SystemProgram.programId,
+        }).rpc();
+
+        const keychain = await keychainProgram.account.currentKeyChain.fetch(keychainPda);
+        assert.equal(keychain.keys.length, 2);
+        const beneficiaryEntry = keychain.keys.find((k) => k.key.equals(beneficiary.publicKey));
+        keychainState = await keychainProgram.account.keyChainState.fetch(keychainStatePda);
+        assert.ok(beneficiaryEntry.weight >= keychainState.actionThreshold, 'beneficiary should be able to act alone');
+        expect(keychainState.beneficiary).to.be.null;
+
+        const keychainKey = await keychainProgram.account.keyChainKey.fetch(beneficiaryKeyPda);
+        assert.ok(keychainKey.keychain.equals(keychainPda));
+    });
+
+    it("adds a key with a relayed intent", async () => {
+        // the key signs the intent, but never pays for (or signs) the transaction
+        const owner = anchor.web3.Keypair.generate();
+        const newKey = anchor.web3.Keypair.generate();
+        const [keychainPda] = findWalletKeychainPda(owner.publicKey, domain, keychainProgram.programId);
+        const [keychainStatePda] = findKeychainStatePda(keychainPda, domain, keychainProgram.programId);
+        const [ownerKeyPda] = findKeychainKeyPda(owner.publicKey, domain, keychainProgram.programId);
+
+        await provider.connection.confirmTransaction(
+            await provider.connection.requestAirdrop(owner.publicKey, anchor.web3.LAMPORTS_PER_SOL * 0.1),
+            "confirmed"
+        );
+        await keychainProgram.methods.createWalletKeychain().accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: ownerKeyPda,
+            domain: domainPda,
+            domainState: domainStatePda,
+            authority: owner.publicKey,
+            systemProgram: SystemProgram.programId,
+            blockedKey: findBlockedKeyPda(owner.publicKey, domain, keychainProgram.programId)[0],
+        }).signers([owner]).rpc();
+
+        const intent = {
+            keychain: keychainPda,
+            action: {addKey: {key: newKey.publicKey}},
+            nonce: new anchor.BN(0),
+            expiry: new anchor.BN(Math.floor(Date.now() / 1000) + 60),
+        };
+        const relayAddKey = () => keychainProgram.methods.relayAddKey(newKey.publicKey, intent).accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            domain: domainPda,
+            blockedKey: findBlockedKeyPda(newKey.publicKey, domain, keychainProgram.programId)[0],
+            authority: provider.wallet.publicKey,
+            instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        }).preInstructions([createIntentSignatureIx(keychainProgram, owner, intent)]).rpc();
+
+        await relayAddKey();
+
+        let keychainState = await keychainProgram.account.keyChainState.fetch(keychainStatePda);
+        assert.ok(keychainState.pendingAction.key.equals(newKey.publicKey), 'add should be pending');
+        assert.equal(keychainState.nonce.toNumber(), 1);
+
+        // clear the pending add, then try replaying the intent
+        await keychainProgram.methods.votePendingAction(false).accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            domain: domainPda,
+            keychainKey: null,
+            authority: owner.publicKey,
+        }).signers([owner]).rpc();
+        try {
+            await relayAddKey();
+            assert.fail("intent shouldn't be usable twice");
+        } catch (err) {
+            expect(err.toString()).to.include('InvalidNonce');
+        }
+
+        // a relayed vote is tied to the pending action it was signed for
+        await keychainProgram.methods.setActionThreshold(1).accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            authority: owner.publicKey,
+        }).signers([owner]).rpc();
+        const relayVote = (action) => {
+            const voteIntent = {
+                keychain: keychainPda,
+                action: {vote: {vote: true, ...action}},
+                nonce: new anchor.BN(1),
+                expiry: new anchor.BN(Math.floor(Date.now() / 1000) + 60),
+            };
+            return keychainProgram.methods.relayVote(true, voteIntent).accounts({
+                keychain: keychainPda,
+                keychainState: keychainStatePda,
+                keychainKey: null,
+                authority: provider.wallet.publicKey,
+                instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+            }).preInstructions([createIntentSignatureIx(keychainProgram, owner, voteIntent)]).rpc();
+        };
+        try {
+            await relayVote({actionType: {withdraw: {mint: null, amount: new anchor.BN(1)}}, key: provider.wallet.publicKey});
+            assert.fail("a vote for another action shouldn't count");
+        } catch (err) {
+            expect(err.toString()).to.include('InvalidIntent');
+        }
+        await relayVote({actionType: {setThreshold: {threshold: 1}}, key: keychainPda});
+
+        keychainState = await keychainProgram.account.keyChainState.fetch(keychainStatePda);
+        expect(keychainState.pendingAction).to.be.null;
+        assert.equal(keychainState.actionThreshold, 1);
+
+        // an intent for another program can only be used by that program (signing with its intent authority pda), so a
+        // copy can't be used directly to burn the nonce
+        const delistIntent = {
+            keychain: keychainPda,
+            action: {delist: {program: anchor.workspace.Yardsale.programId, listing: anchor.web3.Keypair.generate().publicKey}},
+            nonce: new anchor.BN(2),
+            expiry: new anchor.BN(Math.floor(Date.now() / 1000) + 60),
+        };
+        try {
+            await keychainProgram.methods.useIntent(delistIntent).accounts({
+                keychain: keychainPda,
+                keychainState: keychainStatePda,
+                instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+                programSigner: provider.wallet.publicKey,
+            }).preInstructions([createIntentSignatureIx(keychainProgram, owner, delistIntent)]).rpc();
+            assert.fail("only the intent's program should be able to use it");
+        } catch (err) {
+            expect(err.toString()).to.include('InvalidIntent');
+        }
+        keychainState = await keychainProgram.account.keyChainState.fetch(keychainStatePda);
+        assert.equal(keychainState.nonce.toNumber(), 2);
+
+        // a relayed removal of the only key closes the keychain - the rent goes to the signer's wallet, not the relayer
+        const removeIntent = {
+            keychain: keychainPda,
+            action: {removeKey: {key: owner.publicKey}},
+            nonce: new anchor.BN(2),
+            expiry: new anchor.BN(Math.floor(Date.now() / 1000) + 60),
+        };
+        const relayRemoveKey = (wallet) => keychainProgram.methods.relayRemoveKey(owner.publicKey, removeIntent).accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: ownerKeyPda,
+            authority: provider.wallet.publicKey,
+            instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+            wallet,
+            vault: findKeychainVaultPda(keychainPda, domain, keychainProgram.programId)[0],
+            domainState: domainStatePda,
+        }).preInstructions([createIntentSignatureIx(keychainProgram, owner, removeIntent)]).rpc();
+        try {
+            await relayRemoveKey(provider.wallet.publicKey);
+            assert.fail("the relayer shouldn't get the rent");
+        } catch (err) {
+            expect(err.toString()).to.include('InvalidDestination');
+        }
+
+        let rent = 0;
+        for (const account of [keychainPda, keychainStatePda, ownerKeyPda]) {
+            rent += await provider.connection.getBalance(account);
+        }
+        const ownerBalance = await provider.connection.getBalance(owner.publicKey);
+        await relayRemoveKey(owner.publicKey);
+        assert.equal(await provider.connection.getBalance(owner.publicKey), ownerBalance + rent);
+        expect(await provider.connection.getAccountInfo(keychainPda)).to.be.null;
+    });
+
+    it("only verifies program keys for pdas", async () => {
+        const owner = anchor.web3.Keypair.generate();
+        const walletKey = anchor.web3.Keypair.generate();
+        const [keychainPda] = findWalletKeychainPda(owner.publicKey, domain, keychainProgram.programId);
+        const [keychainStatePda] = findKeychainStatePda(keychainPda, domain, keychainProgram.programId);
+        const [ownerKeyPda] = findKeychainKeyPda(owner.publicKey, domain, keychainProgram.programId);
+
+        await provider.connection.confirmTransaction(
+            await provider.connection.requestAirdrop(owner.publicKey, anchor.web3.LAMPORTS_PER_SOL * 0.1),
+            "confirmed"
+        );
+        await keychainProgram.methods.createWalletKeychain().accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: ownerKeyPda,
+            domain: domainPda,
+            domainState: domainStatePda,
+            authority: owner.publicKey,
+            systemProgram: SystemProgram.programId,
+            blockedKey: findBlockedKeyPda(owner.publicKey, domain, keychainProgram.programId)[0],
+        }).signers([owner]).rpc();
+
+        let keychain = await keychainProgram.account.currentKeyChain.fetch(keychainPda);
+        assert.ok(!keychain.keys[0].programControlled, 'a wallet key is not program controlled');
+
+        await keychainProgram.methods.addKey(walletKey.publicKey).accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            domain: domainPda,
+            authority: owner.publicKey,
+            blockedKey: findBlockedKeyPda(walletKey.publicKey, domain, keychainProgram.programId)[0],
+        }).signers([owner]).rpc();
+
+        // a pda can only sign through its program's invoke_signed, so a wallet can't use the program key path
+        try {
+            await keychainProgram.methods.verifyProgramKey().accounts({
+                domain: domainPda,
+                domainState: domainStatePda,
+                blockedKey: findBlockedKeyPda(walletKey.publicKey, domain, keychainProgram.programId)[0],
+                keychain: keychainPda,
+                keychainState: keychainStatePda,
+                keychainKey: findKeychainKeyPda(walletKey.publicKey, domain, keychainProgram.programId)[0],
+                authority: walletKey.publicKey,
+                payer: provider.wallet.publicKey,
+                treasury: treasury.publicKey,
+                systemProgram: SystemProgram.programId,
+            }).signers([walletKey]).rpc();
+            assert.fail("wallet shouldn't verify as a program key");
+        } catch (err) {
+            expect(err.toString()).to.include('NotProgramKey');
+        }
+    });
+
+    it("proves a keychain's token holdings", async () => {
+        const owner = anchor.web3.Keypair.generate();
+        const outsider = anchor.web3.Keypair.generate();
+        const payer = (provider.wallet as Wallet).payer;
+        const [keychainPda] = findWalletKeychainPda(owner.publicKey, domain, keychainProgram.programId);
+        const [keychainStatePda] = findKeychainStatePda(keychainPda, domain, keychainProgram.programId);
+        const [ownerKeyPda] = findKeychainKeyPda(owner.publicKey, domain, keychainProgram.programId);
+
+        await provider.connection.confirmTransaction(
+            await provider.connection.requestAirdrop(owner.publicKey, anchor.web3.LAMPORTS_PER_SOL * 0.1),
+            "confirmed"
+        );
+        await keychainProgram.methods.createWalletKeychain().accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: ownerKeyPda,
+            domain: domainPda,
+            domainState: domainStatePda,
+            authority: owner.publicKey,
+            systemProgram: SystemProgram.programId,
+            blockedKey: findBlockedKeyPda(owner.publicKey, domain, keychainProgram.programId)[0],
+        }).signers([owner]).rpc();
+
+        const mint = await createMint(provider.connection, payer, payer.publicKey, null, 0);
+        const ownerToken = await createAssociatedTokenAccount(provider.connection, payer, mint, owner.publicKey);
+        const outsiderToken = await createAssociatedTokenAccount(provider.connection, payer, mint, outsider.publicKey);
+        await mintToChecked(provider.connection, payer, mint, ownerToken, payer, 5, 0);
+        await mintToChecked(provider.connection, payer, mint, outsiderToken, payer, 3, 0);
+
+        const tokenAccount = (pubkey: PublicKey) => ({pubkey, isSigner: false, isWritable: false});
+
+        // the same token account passed twice only counts once
+        const total = await keychainProgram.methods.getHoldings(mint, false).accounts({
+            keychain: keychainPda,
+        }).remainingAccounts([tokenAccount(ownerToken), tokenAccount(ownerToken)]).view();
+        assert.equal(total.toNumber(), 5);
+
+        // token accounts held by wallets that aren't on the keychain don't count
+        try {
+            await keychainProgram.methods.getHoldings(mint, false).accounts({
+                keychain: keychainPda,
+            }).remainingAccounts([tokenAccount(ownerToken), tokenAccount(outsiderToken)]).view();
+            assert.fail("outsider's tokens shouldn't count");
+        } catch (err) {
+            expect(err.toString()).to.include('InvalidHoldingsAccount');
+        }
+    });
+
+    it("mints a soulbound identity that's burned when its key leaves", async () => {
+        const owner = anchor.web3.Keypair.generate();
+        const [keychainPda] = findWalletKeychainPda(owner.publicKey, domain, keychainProgram.programId);
+        const [keychainStatePda] = findKeychainStatePda(keychainPda, domain, keychainProgram.programId);
+        const [ownerKeyPda] = findKeychainKeyPda(owner.publicKey, domain, keychainProgram.programId);
+        const [identityMint] = findIdentityMintPda(keychainPda, domain, keychainProgram.programId);
+        const identityToken = getAssociatedTokenAddressSync(identityMint, owner.publicKey);
+        const identityPrograms = {
+            tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+            systemProgram: SystemProgram.programId,
+            rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        };
+
+        await provider.connection.confirmTransaction(
+            await provider.connection.requestAirdrop(owner.publicKey, anchor.web3.LAMPORTS_PER_SOL),
+            "confirmed"
+        );
+        await keychainProgram.methods.createWalletKeychain().accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: ownerKeyPda,
+            domain: domainPda,
+            domainState: domainStatePda,
+            authority: owner.publicKey,
+            systemProgram: SystemProgram.programId,
+            blockedKey: findBlockedKeyPda(owner.publicKey, domain, keychainProgram.programId)[0],
+        }).signers([owner]).rpc();
+
+        await keychainProgram.methods.mintIdentity("https://stache.io/identity.json").accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            identityMint,
+            identityToken,
+            previousToken: null,
+            metadata: findMetadataPda(identityMint),
+            authority: owner.publicKey,
+            ...identityPrograms,
+        }).signers([owner]).rpc();
+
+        let tokenAccount = await getAccount(provider.connection, identityToken);
+        assert.equal(Number(tokenAccount.amount), 1);
+        assert.ok(tokenAccount.isFrozen);
+        let keychainState = await keychainProgram.account.keyChainState.fetch(keychainStatePda);
+        assert.ok(keychainState.identityHolder.equals(owner.publicKey));
+
+        // the holder can't mint it to themselves again
+        try {
+            await keychainProgram.methods.mintIdentity("https://stache.io/identity.json").accounts({
+                keychain: keychainPda,
+                keychainState: keychainStatePda,
+                identityMint,
+                identityToken,
+                previousToken: identityToken,
+                metadata: findMetadataPda(identityMint),
+                authority: owner.publicKey,
+                ...identityPrograms,
+            }).signers([owner]).rpc();
+            assert.fail("shouldn't be able to mint the identity twice");
+        } catch (err) {
+            expect(err.toString()).to.include('IdentityAlreadyHeld');
+        }
+
+        // the keychain can't be used to thaw it through a proposal
+        const thawIx = createThawAccountInstruction(identityToken, identityMint, keychainPda);
+        try {
+            await keychainProgram.methods.proposeInstruction(TOKEN_PROGRAM_ID, thawIx.keys.map((meta) => ({
+                pubkey: meta.pubkey, isSigner: meta.isSigner, isWritable: meta.isWritable,
+            })), thawIx.data, false).accounts({
+                keychain: keychainPda,
+                keychainState: keychainStatePda,
+                proposal: findKeychainProposalPda(keychainPda, domain, keychainProgram.programId)[0],
+                authority: owner.publicKey,
+                systemProgram: SystemProgram.programId,
+            }).signers([owner]).rpc();
+            assert.fail("shouldn't be able to propose thawing the identity");
+        } catch (err) {
+            expect(err.toString()).to.include('InvalidProposal');
+        }
+        // or through another program, which could cpi into the token program with the keychain's signature
+        try {
+            await keychainProgram.methods.proposeInstruction(SystemProgram.programId, [
+                {pubkey: identityToken, isSigner: false, isWritable: true},
+                {pubkey: identityMint, isSigner: false, isWritable: false},
+                {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
+            ], Buffer.from([]), false).accounts({
+                keychain: keychainPda,
+                keychainState: keychainStatePda,
+                proposal: findKeychainProposalPda(keychainPda, domain, keychainProgram.programId)[0],
+                authority: owner.publicKey,
+                systemProgram: SystemProgram.programId,
+            }).signers([owner]).rpc();
+            assert.fail("shouldn't be able to pass the identity to another program");
+        } catch (err) {
+            expect(err.toString()).to.include('InvalidProposal');
+        }
+
+        // removing the only key closes the keychain and burns the identity
+        await keychainProgram.methods.removeKey(owner.publicKey).accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: ownerKeyPda,
+            domainState: domainStatePda,
+            authority: owner.publicKey,
+            identityMint,
+            identityToken,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            vault: findKeychainVaultPda(keychainPda, domain, keychainProgram.programId)[0],
+        }).signers([owner]).rpc();
+
+        tokenAccount = await getAccount(provider.connection, identityToken);
+        assert.equal(Number(tokenAccount.amount), 0);
+        const mint = await getMint(provider.connection, identityMint);
+        assert.equal(Number(mint.supply), 0);
+    });
+
+    it("records a keychain's activity once it has a log", async () => {
+        const owner = anchor.web3.Keypair.generate();
+        const [keychainPda] = findWalletKeychainPda(owner.publicKey, domain, keychainProgram.programId);
+        const [keychainStatePda] = findKeychainStatePda(keychainPda, domain, keychainProgram.programId);
+        const [ownerKeyPda] = findKeychainKeyPda(owner.publicKey, domain, keychainProgram.programId);
+        const [activityLogPda, activityLogPdaBump] = findActivityLogPda(keychainPda, domain, keychainProgram.programId);
+
+        await provider.connection.confirmTransaction(
+            await provider.connection.requestAirdrop(owner.publicKey, anchor.web3.LAMPORTS_PER_SOL * 0.1),
+            "confirmed"
+        );
+        await keychainProgram.methods.createWalletKeychain().accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: ownerKeyPda,
+            domain: domainPda,
+            domainState: domainStatePda,
+            authority: owner.publicKey,
+            systemProgram: SystemProgram.programId,
+            blockedKey: findBlockedKeyPda(owner.publicKey, domain, keychainProgram.programId)[0],
+        }).signers([owner]).rpc();
+
+        await keychainProgram.methods.createActivityLog().accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            activityLog: activityLogPda,
+            authority: owner.publicKey,
+            systemProgram: SystemProgram.programId,
+        }).signers([owner]).rpc();
+
+        let keychainState = await keychainProgram.account.keyChainState.fetch(keychainStatePda);
+        assert.ok(keychainState.hasActivityLog);
+
+        // once there's a log, it has to be passed in
+        try {
+            await keychainProgram.methods.updateDisplayName('logged').accounts({
+                keychain: keychainPda,
+                keychainState: keychainStatePda,
+                authority: owner.publicKey,
+            }).signers([owner]).rpc();
+            assert.fail("shouldn't be able to skip the activity log");
+        } catch (err) {
+            expect(err.toString()).to.include('MissingActivityLog');
+        }
+
+        await keychainProgram.methods.updateDisplayName('logged').accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            authority: owner.publicKey,
+            activityLog: activityLogPda,
+        }).signers([owner]).rpc();
+        await keychainProgram.methods.setActionThreshold(1).accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            authority: owner.publicKey,
+            activityLog: activityLogPda,
+        }).signers([owner]).rpc();
+        await keychainProgram.methods.votePendingAction(true).accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: null,
+            authority: owner.publicKey,
+            activityLog: activityLogPda,
+        }).signers([owner]).rpc();
+
+        const activityLog = await keychainProgram.account.activityLog.fetch(activityLogPda);
+        assert.ok(activityLog.keychain.equals(keychainPda));
+        assert.equal(activityLog.bump, activityLogPdaBump);
+        assert.equal(activityLog.count.toNumber(), 4);
+        assert.deepEqual(activityLog.entries.map((entry) => Object.keys(entry.activityType)[0]),
+            ['createActivityLog', 'updateDisplayName', 'setActionThreshold', 'approve']);
+        assert.ok(activityLog.entries.every((entry) => entry.actor.equals(owner.publicKey)));
+
+        // the log gets closed along with the keychain
+        await keychainProgram.methods.removeKey(owner.publicKey).accounts({
+            keychain: keychainPda,
+            keychainState: keychainStatePda,
+            keychainKey: ownerKeyPda,
+            domainState: domainStatePda,
+            authority: owner.publicKey,
+            activityLog: activityLogPda,
+            vault: findKeychainVaultPda(keychainPda, domain, keychainProgram.programId)[0],
+        }).signers([owner]).rpc();
+        expect(await keychainProgram.account.activityLog.fetchNullable(activityLogPda)).to.be.null;
+    });
+
+    it("requires protocol approval for reserved domain names", async () => {
+        const reservedName = 'stache';
+        const [reservedDomainPda] = findDomainPda(reservedName, keychainProgram.programId);
+        const [reservedDomainStatePda] = findDomainStatePda(reservedName, keychainProgram.programId);
+        const [protocolConfigPda] = findProtocolConfigPda(keychainProgram.programId);
+        const upgradeAuthorityAccounts = {
+            authority: provider.wallet.publicKey,
+            systemProgram: SystemProgram.programId,
+            program: keychainProgram.programId,
+            programData: findProgramDataAddress(keychainProgram.programId),
+        };
+
+        const protocolConfig = await keychainProgram.account.protocolConfig.fetch(protocolConfigPda);
+        await keychainProgram.methods.updateProtocolConfig(protocolConfig.domainCreationFee, protocolConfig.treasury, [], [reservedName], true)
+            .accounts({protocolConfig: protocolConfigPda, ...upgradeAuthorityAccounts}).rpc();
+
+        const createReservedDomain = async (domainApproval: PublicKey | null) => keychainProgram.methods.createDomain(reservedName, renameCost).accounts({
+            domain: reservedDomainPda,
+            domainState: reservedDomainStatePda,
+            ...(await getCreateDomainAccounts(keychainProgram)),
+            domainApproval,
+            authority: provider.wallet.publicKey,
+            systemProgram: SystemProgram.programId,
+            treasury: treasury.publicKey
+        }).rpc();
+
+        try {
+            await createReservedDomain(null);
+            assert.fail("shouldn't be able to claim a reserved name");
+        } catch (err) {
+            expect(err.toString()).to.include('ReservedName');
+        }
+
+        const [domainApprovalPda] = findDomainApprovalPda(reservedName, keychainProgram.programId);
+        await keychainProgram.methods.approveDomain(reservedName, provider.wallet.publicKey)
+            .accounts({domainApproval: domainApprovalPda, ...upgradeAuthorityAccounts}).rpc();
+
+        await createReservedDomain(domainApprovalPda);
+
+        const reservedDomain = await keychainProgram.account.currentDomain.fetch(reservedDomainPda);
+        assert.ok(reservedDomain.authority.equals(provider.wallet.publicKey));
+        // the approval gets used up
+        assert.ok(await provider.connection.getAccountInfo(domainApprovalPda) == null);
+
+        await keychainProgram.methods.updateProtocolConfig(protocolConfig.domainCreationFee, protocolConfig.treasury, [], [], true)
+            .accounts({protocolConfig: protocolConfigPda, ...upgradeAuthorityAccounts}).rpc();
+    });
+
     it('destroys the domain', async () => {
 
         // program.state.address()
@@ -12,11 +12,15 @@ import {
 import {
   createNFT, createpNFT, createTokenMint,
   findDomainPda,
+  findBlockedKeyPda,
+  getCreateDomainAccounts,
+  initializeKeychain,
   findDomainStatePda,
   findKeychainKeyPda,
   findKeychainPda,
   findKeychainStatePda,
   findListingPda, sleep,
+  logComputeUnits,
 } from "./utils";
 import {
   createAssociatedTokenAccountInstruction,
@@ -159,9 +163,12 @@ describe("yardsale pnfts",  () => {
     console.log(`creating keychain domain: ${domain}...`);
 
     // first create the domain
+    await initializeKeychain(KeychainProgram, provider, treasury, renameCost);
+
     txid = await KeychainProgram.methods.createDomain(domain, renameCost).accounts({
       domain: domainPda,
       domainState: domainStatePda,
+      ...(await getCreateDomainAccounts(KeychainProgram)),
       authority: provider.wallet.publicKey,
       systemProgram: SystemProgram.programId,
       treasury: treasury
@@ -176,9 +183,11 @@ describe("yardsale pnfts",  () => {
       keychainState: userKeychainStatePda,
       keychainKey: userKeychainKeyPda,
       domain: domainPda,
+      domainState: domainStatePda,
       authority: provider.wallet.publicKey,
       wallet: provider.wallet.publicKey,
       systemProgram: SystemProgram.programId,
+      blockedKey: findBlockedKeyPda(provider.wallet.publicKey, domain, KeychainProgram.programId)[0],
     }).rpc();
 
     console.log(`created keychain for ${username}. tx: ${txid}`);
@@ -238,6 +247,8 @@ describe("yardsale pnfts",  () => {
 
 
   console.log(`listed pNFT ${pnft.toBase58()} in tx: ${txid}`);
+    // listing used to re-derive the pnft's metadata address
+    await logComputeUnits(connection, txid, 'list pnft');
     console.log(`creatorItemToken: ${sellerItemToken.toBase58()}`);
     console.log(`listingPda: ${listingPda.toBase58()}`);
     console.log(`listingItemToken: ${listingItemToken.toBase58()}`);
@@ -303,6 +314,7 @@ describe("yardsale pnfts",  () => {
 
     console.log(`---------- purchased! ------------ `);
     console.log(`bought pNFT ${pnft.toBase58()} in tx: ${txid}`);
+    await logComputeUnits(connection, txid, 'purchase pnft');
     console.log(`buyer: ${buyer.publicKey.toBase58()}`);
 
     await sleep(500);
@@ -399,6 +411,7 @@ describe("yardsale pnfts",  () => {
 
     console.log(`---------- delisted! ------------ `);
     console.log(`delisted pNFT ${pnft.toBase58()} in tx: ${txid}`);
+    await logComputeUnits(connection, txid, 'delist pnft');
 
     await sleep(500);
 
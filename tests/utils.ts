@@ -1,4 +1,4 @@
-import {BlockhashWithExpiryBlockHeight, Connection, Keypair, PublicKey, sendAndConfirmTransaction, SystemProgram, Transaction} from "@solana/web3.js";
+import {BlockhashWithExpiryBlockHeight, Connection, Ed25519Program, Keypair, PublicKey, sendAndConfirmTransaction, SystemProgram, Transaction, TransactionInstruction} from "@solana/web3.js";
 import * as anchor from "@project-serum/anchor";
 import {
   createAssociatedTokenAccountInstruction,
@@ -12,17 +12,28 @@ import { Keychain } from "../target/types/keychain";
 import { Profile } from "../target/types/profile";
 import { Yardsale } from "../target/types/yardsale";
 import {Metaplex, WalletAdapter, walletAdapterIdentity} from "@metaplex-foundation/js";
-import {TokenStandard} from "@metaplex-foundation/mpl-token-metadata";
+import {PROGRAM_ID as TOKEN_METADATA_PROGRAM_ID, TokenStandard} from "@metaplex-foundation/mpl-token-metadata";
 
 export const DOMAIN = 'domination';
 export const KEYCHAIN = 'keychain';
 export const YARDSALE = 'yardsale';
 
 export const DOMAIN_STATE = 'domain_state';
+export const DOMAIN_REGISTRY = 'domain_registry';
+export const PROTOCOL_CONFIG = 'protocol_config';
+export const DOMAIN_APPROVAL_SPACE = 'domain_approvals';
+export const GLOBAL_DOMAIN = 'global';
 
 export const KEYCHAIN_SPACE = 'keychains';
 export const KEYCHAIN_STATE_SPACE = 'keychain_states';
 export const KEY_SPACE = 'keys';
+export const VAULT_SPACE = 'vaults';
+export const PROPOSAL_SPACE = 'proposals';
+export const BLOCKLIST_SPACE = 'blocklist';
+export const IDENTITY_SPACE = 'identities';
+export const ACTIVITY_SPACE = 'activity';
+
+export const INTENT_PREFIX = 'keychain_intent';
 
 export const LISTINGS_SPACE = 'listings';
 
@@ -181,6 +192,97 @@ export const findDomainPda = (domain: string, keychainprogid: PublicKey): [Publi
   );
 }
 
+export const findDomainRegistryPda = (keychainprogid: PublicKey): [PublicKey, number] => {
+  return anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from(anchor.utils.bytes.utf8.encode(DOMAIN_REGISTRY)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(KEYCHAIN))],
+      keychainprogid
+  );
+}
+
+export const findProtocolConfigPda = (keychainprogid: PublicKey): [PublicKey, number] => {
+  return anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from(anchor.utils.bytes.utf8.encode(PROTOCOL_CONFIG)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(KEYCHAIN))],
+      keychainprogid
+  );
+}
+
+export const findDomainApprovalPda = (domain: string, keychainprogid: PublicKey): [PublicKey, number] => {
+  return anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from(anchor.utils.bytes.utf8.encode(domain)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(DOMAIN_APPROVAL_SPACE)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(KEYCHAIN))],
+      keychainprogid
+  );
+}
+
+// the accounts create_domain needs on top of the domain itself (registry, protocol config + fee treasury)
+export async function getCreateDomainAccounts(keychainProgram: Program<Keychain>) {
+  const [protocolConfigPda] = findProtocolConfigPda(keychainProgram.programId);
+  const protocolConfig = await keychainProgram.account.protocolConfig.fetch(protocolConfigPda);
+  return {
+    domainRegistry: findDomainRegistryPda(keychainProgram.programId)[0],
+    protocolConfig: protocolConfigPda,
+    protocolTreasury: protocolConfig.treasury,
+    domainApproval: null,
+  };
+}
+
+// the upgradeable loader's program data account (holds the upgrade authority)
+export const findProgramDataAddress = (progid: PublicKey): PublicKey => {
+  return anchor.web3.PublicKey.findProgramAddressSync(
+      [progid.toBuffer()],
+      new PublicKey('BPFLoaderUpgradeab1e11111111111111111111111')
+  )[0];
+}
+
+// creates the domain registry, protocol config + global domain if this is a fresh deploy (the provider wallet needs to be the upgrade authority)
+export async function initializeKeychain(keychainProgram: Program<Keychain>, provider: AnchorProvider, treasury: PublicKey, keyCost: anchor.BN) {
+  const [domainRegistryPda] = findDomainRegistryPda(keychainProgram.programId);
+  if (await provider.connection.getAccountInfo(domainRegistryPda) != null) {
+    return;
+  }
+
+  const [globalDomainPda] = findDomainPda(GLOBAL_DOMAIN, keychainProgram.programId);
+  const [globalDomainStatePda] = findDomainStatePda(GLOBAL_DOMAIN, keychainProgram.programId);
+
+  await keychainProgram.methods.initialize(keyCost).accounts({
+    domainRegistry: domainRegistryPda,
+    protocolConfig: findProtocolConfigPda(keychainProgram.programId)[0],
+    domain: globalDomainPda,
+    domainState: globalDomainStatePda,
+    treasury,
+    authority: provider.wallet.publicKey,
+    systemProgram: SystemProgram.programId,
+    program: keychainProgram.programId,
+    programData: findProgramDataAddress(keychainProgram.programId),
+  }).rpc();
+}
+
+// lists every registered domain (skipping any that have since been closed)
+export async function listDomains(keychainProgram: Program<Keychain>) {
+  const [domainRegistryPda] = findDomainRegistryPda(keychainProgram.programId);
+  const registry = await keychainProgram.account.domainRegistry.fetch(domainRegistryPda);
+  const domains = await keychainProgram.account.currentDomain.fetchMultiple(registry.domains);
+  return registry.domains
+      .map((address, i) => ({address, domain: domains[i]}))
+      .filter(d => d.domain != null);
+}
+
+// the domain's blocklist entry for a wallet (only exists if the wallet is blocked)
+export const findBlockedKeyPda = (walletAddress: PublicKey, domain: string, keychainprogid: PublicKey): [PublicKey, number] => {
+  return anchor.web3.PublicKey.findProgramAddressSync(
+      [
+        walletAddress.toBuffer(),
+        Buffer.from(anchor.utils.bytes.utf8.encode(BLOCKLIST_SPACE)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(domain)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(KEYCHAIN)),
+      ],
+      keychainprogid,
+  );
+};
+
 export const findDomainStatePda = (domain: string, keychainprogid: PublicKey): [PublicKey, number] => {
   return anchor.web3.PublicKey.findProgramAddressSync(
       [
@@ -204,6 +306,19 @@ export const findKeychainPda = (name: string, domain: string, keychainprogid: Pu
   );
 };
 
+// finds the keychain pda for a wallet (name-less) keychain
+export const findWalletKeychainPda = (walletAddress: PublicKey, domain: string, keychainprogid: PublicKey): [PublicKey, number] => {
+  return anchor.web3.PublicKey.findProgramAddressSync(
+      [
+        walletAddress.toBuffer(),
+        Buffer.from(anchor.utils.bytes.utf8.encode(KEYCHAIN_SPACE)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(domain)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(KEYCHAIN)),
+      ],
+      keychainprogid,
+  );
+};
+
 export const findKeychainStatePda = (keychainPda: PublicKey, domain: string, keychainprogid: PublicKey): [PublicKey, number] => {
   return anchor.web3.PublicKey.findProgramAddressSync(
       [
@@ -216,6 +331,61 @@ export const findKeychainStatePda = (keychainPda: PublicKey, domain: string, key
   );
 };
 
+export const findKeychainVaultPda = (keychainPda: PublicKey, domain: string, keychainprogid: PublicKey): [PublicKey, number] => {
+  return anchor.web3.PublicKey.findProgramAddressSync(
+      [
+        keychainPda.toBuffer(),
+        Buffer.from(anchor.utils.bytes.utf8.encode(VAULT_SPACE)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(domain)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(KEYCHAIN)),
+      ],
+      keychainprogid,
+  );
+};
+
+export const findIdentityMintPda = (keychainPda: PublicKey, domain: string, keychainprogid: PublicKey): [PublicKey, number] => {
+  return anchor.web3.PublicKey.findProgramAddressSync(
+      [
+        keychainPda.toBuffer(),
+        Buffer.from(anchor.utils.bytes.utf8.encode(IDENTITY_SPACE)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(domain)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(KEYCHAIN)),
+      ],
+      keychainprogid,
+  );
+};
+
+export const findActivityLogPda = (keychainPda: PublicKey, domain: string, keychainprogid: PublicKey): [PublicKey, number] => {
+  return anchor.web3.PublicKey.findProgramAddressSync(
+      [
+        keychainPda.toBuffer(),
+        Buffer.from(anchor.utils.bytes.utf8.encode(ACTIVITY_SPACE)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(domain)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(KEYCHAIN)),
+      ],
+      keychainprogid,
+  );
+};
+
+export const findMetadataPda = (mint: PublicKey): PublicKey => {
+  return anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from('metadata'), TOKEN_METADATA_PROGRAM_ID.toBuffer(), mint.toBuffer()],
+      TOKEN_METADATA_PROGRAM_ID,
+  )[0];
+};
+
+export const findKeychainProposalPda = (keychainPda: PublicKey, domain: string, keychainprogid: PublicKey): [PublicKey, number] => {
+  return anchor.web3.PublicKey.findProgramAddressSync(
+      [
+        keychainPda.toBuffer(),
+        Buffer.from(anchor.utils.bytes.utf8.encode(PROPOSAL_SPACE)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(domain)),
+        Buffer.from(anchor.utils.bytes.utf8.encode(KEYCHAIN)),
+      ],
+      keychainprogid,
+  );
+};
+
 // find the keychain KEY pda for the given wallet address (for the domination domain)
 export const findKeychainKeyPda = (walletAddress: PublicKey, domain: string, keychainprogid: PublicKey): [PublicKey, number] => {
   // const [keychainPda, keychainPdaBump] = anchor.web3.PublicKey.findProgramAddressSync(
@@ -253,6 +423,21 @@ export const findListingPda = (nftMint: PublicKey, keychainName: string, domain:
   );
 }
 
+// the ed25519 signature check for a relayed intent - goes right before the relay instruction
+export function createIntentSignatureIx(keychainProgram: Program<Keychain>, signer: Keypair, intent): TransactionInstruction {
+  const message = Buffer.concat([Buffer.from(INTENT_PREFIX), keychainProgram.coder.types.encode('Intent', intent)]);
+  return Ed25519Program.createInstructionWithPrivateKey({privateKey: signer.secretKey, message});
+}
+
+// logs the compute units a confirmed transaction used (for comparing instruction costs between builds)
+export async function logComputeUnits(connection: Connection, txid: string, label: string): Promise<number> {
+  await connection.confirmTransaction(txid, 'confirmed');
+  const tx = await connection.getTransaction(txid, {commitment: 'confirmed', maxSupportedTransactionVersion: 0});
+  const units = tx?.meta?.computeUnitsConsumed;
+  console.log(`${label} compute units: ${units}`);
+  return units;
+}
+
 export function sleep(ms) {
   return new Promise(resolve => setTimeout(resolve, ms));
 }
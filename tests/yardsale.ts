@@ -12,6 +12,9 @@ import {
 import {
   createNFT, createTokenMint,
   findDomainPda,
+  findBlockedKeyPda,
+  getCreateDomainAccounts,
+  initializeKeychain,
   findDomainStatePda,
   findKeychainKeyPda,
   findKeychainPda,
@@ -147,9 +150,12 @@ describe("yardsale", () => {
     console.log(`creating keychain domain: ${domain}...`);
 
     // first create the domain
+    await initializeKeychain(keychainProgram, provider, treasury.publicKey, renameCost);
+
     txid = await keychainProgram.methods.createDomain(domain, renameCost).accounts({
       domain: domainPda,
       domainState: domainStatePda,
+      ...(await getCreateDomainAccounts(keychainProgram)),
       authority: provider.wallet.publicKey,
       systemProgram: SystemProgram.programId,
       treasury: treasury.publicKey
@@ -164,9 +170,11 @@ describe("yardsale", () => {
       keychainState: userKeychainStatePda,
       keychainKey: userKeychainKeyPda,
       domain: domainPda,
+      domainState: domainStatePda,
       authority: provider.wallet.publicKey,
       wallet: provider.wallet.publicKey,
       systemProgram: SystemProgram.programId,
+      blockedKey: findBlockedKeyPda(provider.wallet.publicKey, domain, keychainProgram.programId)[0],
     }).rpc();
 
     console.log(`created keychain for ${username}. tx: ${txid}`);